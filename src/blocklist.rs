@@ -0,0 +1,351 @@
+/*
+    Keeping offensive words out of passphrases.
+
+    Blocked words are pruned from a trained model rather than replaced in its output:
+    every context that contains a blocked word and every transition to one is removed,
+    and the successor distributions are rebuilt from the counts that are left. The
+    chain then never reaches a blocked word, and the entropy the generator reports is
+    the entropy of the pruned model, the one it actually draws from.
+*/
+
+use crate::distribution::Distribution;
+use crate::entropy::SamplingMode;
+use crate::error::PassphraseError;
+use crate::hash::Fnv1a;
+use crate::model::{line_starts, Context, TransitionModel};
+use crate::tokenizer::BOUNDARY;
+use crate::vocabulary::{Vocabulary, WordId};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/* The built-in list of profanity, sexual terms and slurs. */
+const BUILTIN: &str = include_str!("blocklist.txt");
+
+/* The letters that digits and symbols stand for in leet spellings such as "5h1t". */
+const LEET: [(char, char); 13] = [
+    ('0', 'o'),
+    ('1', 'i'),
+    ('!', 'i'),
+    ('|', 'i'),
+    ('3', 'e'),
+    ('4', 'a'),
+    ('@', 'a'),
+    ('5', 's'),
+    ('$', 's'),
+    ('7', 't'),
+    ('+', 't'),
+    ('8', 'b'),
+    ('9', 'g'),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+struct Entry {
+    letters: String,
+    /* Whether the entry started or ended with *, matching any letters there. */
+    any_before: bool,
+    any_after: bool,
+}
+
+impl Entry {
+    fn matches(&self, key: &str) -> bool {
+        match (self.any_before, self.any_after) {
+            (false, false) => key == self.letters,
+            (false, true) => key.starts_with(&self.letters),
+            (true, false) => key.ends_with(&self.letters),
+            (true, true) => key.contains(&self.letters),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Blocklist {
+    entries: Vec<Entry>,
+    /*
+        Also match leet spellings, where digits and symbols stand for letters, and
+        letters repeated for emphasis, as in "shiiit".
+    */
+    leet: bool,
+}
+
+impl Blocklist {
+    pub fn new(leet: bool) -> Blocklist {
+        Blocklist {
+            entries: Vec::new(),
+            leet,
+        }
+    }
+
+    pub fn builtin(leet: bool) -> Blocklist {
+        let mut blocklist = Blocklist::new(leet);
+        blocklist.add_builtin();
+        blocklist
+    }
+
+    pub fn add_builtin(&mut self) {
+        /* Blocks the profanity, sexual terms and slurs of the built-in list. */
+        self.add_list(BUILTIN);
+    }
+
+    pub fn add_word(&mut self, word: &str) {
+        /*
+            Blocks a word, ignoring case. A * at the start or end of the word matches
+            any letters there: "fuck*" blocks "fucking" and "*fuck*" blocks
+            "motherfucker". An entry with no letters is ignored.
+        */
+        let any_before = word.starts_with('*');
+        let any_after = word.len() > 1 && word.ends_with('*');
+        let letters = self.key(word.trim_matches('*'));
+        if letters.is_empty() {
+            return;
+        }
+        let entry = Entry {
+            letters,
+            any_before,
+            any_after,
+        };
+        if !self.entries.contains(&entry) {
+            self.entries.push(entry);
+        }
+    }
+
+    pub fn add_list(&mut self, text: &str) {
+        /*
+            Blocks every word of a list with one word per line. Lines starting with #
+            are comments.
+        */
+        for line in text.lines().map(str::trim) {
+            if !line.is_empty() && !line.starts_with('#') {
+                self.add_word(line);
+            }
+        }
+    }
+
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), PassphraseError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|source| PassphraseError::MissingCorpus {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        self.add_list(&text);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn key(&self, word: &str) -> String {
+        /*
+            The form a word is compared in: lowercase, and with leet matching, with
+            digits and symbols read as the letters they stand for.
+        */
+        word.chars()
+            .flat_map(char::to_lowercase)
+            .map(
+                |c| match LEET.iter().find(|&&(leet, _)| self.leet && leet == c) {
+                    Some(&(_, letter)) => letter,
+                    None => c,
+                },
+            )
+            .collect()
+    }
+
+    fn keys(&self, word: &str) -> Vec<String> {
+        /*
+            A function to list the forms of a word to compare with the entries. The
+            part before an apostrophe is compared too, so blocking "dick" also blocks
+            "dick's" and "dick'll". With leet matching, a letter repeated three or
+            more times is also read once and twice, so "shiiit" is read as "shit".
+        */
+        let mut keys = vec![self.key(word)];
+        if let Some((stem, _)) = word.split_once('\'') {
+            keys.push(self.key(stem));
+        }
+        if self.leet {
+            for key in keys.clone() {
+                for repeats in [1, 2] {
+                    keys.push(collapsed(&key, repeats));
+                }
+            }
+        }
+        keys.sort_unstable();
+        keys.dedup();
+        keys
+    }
+
+    pub fn blocks(&self, word: &str) -> bool {
+        self.keys(word)
+            .iter()
+            .any(|key| self.entries.iter().any(|entry| entry.matches(key)))
+    }
+}
+
+fn collapsed(key: &str, repeats: usize) -> String {
+    /*
+        A function to shorten every run of three or more of the same character to
+        `repeats` characters.
+    */
+    let chars = key.chars().collect::<Vec<char>>();
+    let mut result = String::new();
+    let mut start = 0;
+    while start < chars.len() {
+        let run = chars[start..]
+            .iter()
+            .take_while(|&&c| c == chars[start])
+            .count();
+        let kept = if run >= 3 { repeats } else { run };
+        result.extend(std::iter::repeat_n(chars[start], kept));
+        start += run;
+    }
+    result
+}
+
+#[derive(Clone, Debug)]
+pub struct Pruning {
+    /* The words removed from the model, in the order the model first saw them. */
+    pub words: Vec<String>,
+    /* How often they occurred in the training text. */
+    pub occurrences: usize,
+    /* The Shannon entropy of a frequency-weighted start state, before and after pruning. */
+    pub start_before: f64,
+    pub start_after: f64,
+    /*
+        The Shannon entropy of a frequency-weighted step of the chain, averaged over the
+        contexts of the training text by how often each occurs, before and after.
+    */
+    pub step_before: f64,
+    pub step_after: f64,
+}
+
+fn step_entropy(model: &TransitionModel) -> f64 {
+    let mut total = 0.0;
+    let mut shannon_bits = 0.0;
+    for successors in model.transitions.values() {
+        let weight = successors.total() as f64;
+        total += weight;
+        shannon_bits += weight * successors.entropy(SamplingMode::Weighted);
+    }
+    match total > 0.0 {
+        true => shannon_bits / total,
+        false => 0.0,
+    }
+}
+
+fn remapped(context: &Context, ids: &[Option<WordId>]) -> Option<Context> {
+    /* The context in the pruned vocabulary, or None if it contains a blocked word. */
+    context
+        .words()
+        .iter()
+        .map(|word| ids[word.0 as usize])
+        .collect::<Option<Vec<WordId>>>()
+        .map(Context::new)
+}
+
+fn prune_table(
+    table: &HashMap<Context, Distribution<WordId>>,
+    ids: &[Option<WordId>],
+) -> HashMap<Context, Distribution<WordId>> {
+    /*
+        A function to drop the contexts that contain a blocked word and the blocked
+        successors of the rest. A context left without successors is dropped too,
+        so it becomes a dead end.
+    */
+    table
+        .iter()
+        .filter_map(|(context, successors)| {
+            let context = remapped(context, ids)?;
+            let successors = Distribution::from_counts(
+                successors
+                    .iter()
+                    .filter_map(|(word, count)| Some((ids[word.0 as usize]?, count))),
+            );
+            (!successors.is_empty()).then_some((context, successors))
+        })
+        .collect()
+}
+
+impl TransitionModel {
+    pub fn prune(&mut self, blocklist: &Blocklist) -> Result<Pruning, PassphraseError> {
+        /*
+            A function to remove every word the blocklist blocks from the model, with
+            the contexts and start states that contain it. The training text is split
+            at the removed words, like at a filtered token, and the corpus hash becomes
+            a hash of the old one and the removed words.
+        */
+        let start_before = self.start_entropy();
+        let step_before = step_entropy(self);
+        let mut vocabulary = Vocabulary::new();
+        let mut words = Vec::new();
+        let ids = self
+            .vocabulary
+            .words()
+            .iter()
+            .map(|word| match word != BOUNDARY && blocklist.blocks(word) {
+                true => {
+                    words.push(word.clone());
+                    None
+                }
+                false => Some(vocabulary.intern(word)),
+            })
+            .collect::<Vec<Option<WordId>>>();
+        let occurrences = self
+            .documents
+            .iter()
+            .flatten()
+            .filter(|word| ids[word.0 as usize].is_none())
+            .count();
+        if !words.is_empty() {
+            let starts = Distribution::from_counts(
+                self.starts
+                    .iter()
+                    .filter_map(|(context, count)| Some((remapped(context, &ids)?, count))),
+            );
+            if starts.is_empty() {
+                return Err(PassphraseError::NoReachableStates(
+                    "the blocklist removes every start state of the model".to_string(),
+                ));
+            }
+            self.starts = starts;
+            self.transitions = prune_table(&self.transitions, &ids);
+            self.backoff = self
+                .backoff
+                .iter()
+                .map(|table| prune_table(table, &ids))
+                .collect();
+            self.vocabulary = vocabulary;
+            self.line_starts = line_starts(&self.transitions, self.order, self.boundary());
+            self.documents = self
+                .documents
+                .iter()
+                .flat_map(|document| document.split(|word| ids[word.0 as usize].is_none()))
+                .filter(|segment| !segment.is_empty())
+                .map(|segment| {
+                    segment
+                        .iter()
+                        .filter_map(|word| ids[word.0 as usize])
+                        .collect()
+                })
+                .collect();
+            let mut hasher = Fnv1a::new();
+            hasher.write(&self.corpus_hash.to_le_bytes());
+            for word in &words {
+                hasher.write(word.as_bytes());
+                hasher.write(&[0]);
+            }
+            self.corpus_hash = hasher.finish();
+        }
+        Ok(Pruning {
+            words,
+            occurrences,
+            start_before,
+            start_after: self.start_entropy(),
+            step_before,
+            step_after: step_entropy(self),
+        })
+    }
+}
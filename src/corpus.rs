@@ -0,0 +1,368 @@
+/*
+    Loading and normalizing training text.
+*/
+
+use crate::error::PassphraseError;
+use crate::filter::TokenFilter;
+use crate::hash::Fnv1a;
+use crate::tokenizer::{Tokenizer, BOUNDARY};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/*
+    The number of steps the smallest weighted source is split into, so that the ratio
+    between two weights is kept to within about 1% when counts are scaled by whole numbers.
+*/
+pub(crate) const WEIGHT_RESOLUTION: f64 = 100.0;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Source {
+    /* A name for messages, such as the path the text was read from. */
+    pub name: String,
+    /*
+        The share of the training data this source makes up, relative to the weights of
+        the other sources. None keeps its natural share, in proportion to its size.
+    */
+    pub weight: Option<f64>,
+}
+
+pub struct Corpus {
+    /*
+        The normalized words of each training text, in order. Documents are kept apart
+        so that the chain never learns transitions across the end of one file and the
+        start of the next.
+    */
+    documents: Vec<Vec<String>>,
+    /* The source each document belongs to, an index into sources. */
+    document_sources: Vec<usize>,
+    sources: Vec<Source>,
+    tokenizer: Tokenizer,
+    filter: TokenFilter,
+}
+
+impl Corpus {
+    pub fn new() -> Corpus {
+        Corpus::with_tokenizer(Tokenizer::default())
+    }
+
+    pub fn with_tokenizer(tokenizer: Tokenizer) -> Corpus {
+        /*
+            An empty corpus that splits the text added to it with the given tokenizer.
+            The tokenizer is stored with any model trained on the corpus.
+        */
+        Corpus {
+            documents: Vec::new(),
+            document_sources: Vec::new(),
+            sources: Vec::new(),
+            tokenizer,
+            filter: TokenFilter::default(),
+        }
+    }
+
+    pub fn from_text(text: &str) -> Corpus {
+        let mut corpus = Corpus::new();
+        corpus.add_text(text);
+        corpus
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Corpus, PassphraseError> {
+        let mut corpus = Corpus::new();
+        corpus.add_file(path)?;
+        Ok(corpus)
+    }
+
+    pub fn add_source(&mut self, name: &str, weight: Option<f64>) -> Result<(), PassphraseError> {
+        /*
+            Starts a new source: the texts added from now on belong to it. Either every
+            source has a weight or none does, since a natural share cannot be compared
+            with a weighted one. Texts added before the first source belong to an
+            unweighted one.
+        */
+        if weight.is_some_and(|weight| !(weight.is_finite() && weight >= 0.0)) {
+            return Err(PassphraseError::Usage(format!(
+                "the weight of {name} must be a non-negative number"
+            )));
+        }
+        if self
+            .sources
+            .iter()
+            .any(|source| source.weight.is_some() != weight.is_some())
+        {
+            return Err(PassphraseError::Usage(
+                "either every corpus source or none must have a weight".to_string(),
+            ));
+        }
+        self.sources.push(Source {
+            name: name.to_string(),
+            weight,
+        });
+        Ok(())
+    }
+
+    pub fn add_text(&mut self, text: &str) {
+        if self.sources.is_empty() {
+            self.sources.push(Source {
+                name: "corpus".to_string(),
+                weight: None,
+            });
+        }
+        self.documents.push(self.tokenizer.tokenize_document(text));
+        self.document_sources.push(self.sources.len() - 1);
+    }
+
+    pub fn add_file<P: AsRef<Path>>(&mut self, path: P) -> Result<(), PassphraseError> {
+        /*
+            A function to read training data from a file.
+            Note: the file should contain a list of words separated by whitespace.
+        */
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(text) => {
+                self.add_text(&text);
+                Ok(())
+            }
+            Err(source) => Err(PassphraseError::MissingCorpus {
+                path: path.to_path_buf(),
+                source,
+            }),
+        }
+    }
+
+    pub fn add_reader<R: Read>(
+        &mut self,
+        mut reader: R,
+        name: &str,
+    ) -> Result<(), PassphraseError> {
+        /*
+            A function to read training data from a stream such as stdin.
+            The name is only used in error messages.
+        */
+        let mut text = String::new();
+        match reader.read_to_string(&mut text) {
+            Ok(_) => {
+                self.add_text(&text);
+                Ok(())
+            }
+            Err(source) => Err(PassphraseError::MissingCorpus {
+                path: PathBuf::from(name),
+                source,
+            }),
+        }
+    }
+
+    pub fn add_dir<P: AsRef<Path>>(&mut self, dir: P) -> Result<(), PassphraseError> {
+        /*
+            A function to read every regular file in a directory, in file name order.
+            Subdirectories are not searched.
+        */
+        let dir = dir.as_ref();
+        let missing = |source| PassphraseError::MissingCorpus {
+            path: dir.to_path_buf(),
+            source,
+        };
+        let mut paths = Vec::new();
+        for entry in fs::read_dir(dir).map_err(missing)? {
+            let path = entry.map_err(missing)?.path();
+            if path.is_file() {
+                paths.push(path);
+            }
+        }
+        paths.sort();
+        for path in paths {
+            self.add_file(path)?;
+        }
+        Ok(())
+    }
+
+    pub fn add_path<P: AsRef<Path>>(&mut self, path: P) -> Result<(), PassphraseError> {
+        /*
+            A function to read a file, or every file in a directory.
+        */
+        let path = path.as_ref();
+        if path.is_dir() {
+            self.add_dir(path)
+        } else {
+            self.add_file(path)
+        }
+    }
+
+    pub fn set_filter(&mut self, filter: TokenFilter) {
+        /*
+            Sets the filter applied when the corpus is trained. Filtering is deferred
+            until then because the minimum frequency depends on the whole corpus.
+        */
+        self.filter = filter;
+    }
+
+    pub fn filter(&self) -> &TokenFilter {
+        &self.filter
+    }
+
+    pub fn segments(&self) -> Vec<Vec<String>> {
+        /*
+            The runs of words that survive the token filter, which are what a model is
+            trained on.
+        */
+        self.filter.segments(&self.documents)
+    }
+
+    pub fn sources(&self) -> &[Source] {
+        &self.sources
+    }
+
+    pub fn weighted_segments(&self) -> Vec<(Vec<String>, u32)> {
+        /*
+            A function to pair every segment with the whole number its counts are scaled
+            by when the model is trained. Sources are scaled so that each makes up its
+            weight's share of the words, however large its text is. Without weights,
+            every segment counts once.
+        */
+        let document_segments = self.filter.document_segments(&self.documents);
+        let totals = self.source_totals(&document_segments);
+        let multipliers = multipliers(&self.sources, &totals);
+        document_segments
+            .into_iter()
+            .zip(&self.document_sources)
+            .flat_map(|(segments, &source)| {
+                let multiplier = multipliers[source];
+                segments
+                    .into_iter()
+                    .map(move |segment| (segment, multiplier))
+            })
+            .collect()
+    }
+
+    fn source_totals(&self, document_segments: &[Vec<Vec<String>>]) -> Vec<usize> {
+        /* The number of words each source has left after filtering. */
+        let mut totals = vec![0; self.sources.len()];
+        for (segments, &source) in document_segments.iter().zip(&self.document_sources) {
+            totals[source] += segments
+                .iter()
+                .flatten()
+                .filter(|word| word.as_str() != BOUNDARY)
+                .count();
+        }
+        totals
+    }
+
+    pub fn weight_scale(&self) -> Option<f64> {
+        /*
+            The count a source of weight 1 adds up to once its counts are scaled, or None
+            without weights. A model records it, so that the counts of text added later
+            can be scaled to match the weights it was trained with.
+        */
+        if self.sources.iter().all(|source| source.weight.is_none()) {
+            return None;
+        }
+        let totals = self.source_totals(&self.filter.document_segments(&self.documents));
+        let counts = multipliers(&self.sources, &totals)
+            .iter()
+            .zip(&totals)
+            .map(|(&multiplier, &total)| multiplier as f64 * total as f64)
+            .sum::<f64>();
+        let weights = self
+            .sources
+            .iter()
+            .filter_map(|source| source.weight)
+            .sum::<f64>();
+        Some(match weights > 0.0 {
+            true => counts / weights,
+            false => 0.0,
+        })
+    }
+
+    pub fn fingerprint(&self) -> u64 {
+        /*
+            A hash of the filtered words, used to tell which corpus a model was trained on.
+            Weights other than 1 are part of the hash, since they change the model.
+        */
+        let mut hasher = Fnv1a::new();
+        for (words, multiplier) in &self.weighted_segments() {
+            for word in words {
+                hasher.write(word.as_bytes());
+                hasher.write(&[0]);
+            }
+            if *multiplier != 1 {
+                hasher.write(&multiplier.to_le_bytes());
+            }
+            hasher.write(&[1]);
+        }
+        hasher.finish()
+    }
+
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    pub fn documents(&self) -> &[Vec<String>] {
+        /*
+            The normalized words of each text, before filtering, with BOUNDARY markers
+            if the tokenizer has line or sentence boundaries on.
+        */
+        &self.documents
+    }
+
+    pub fn len(&self) -> usize {
+        self.documents
+            .iter()
+            .flatten()
+            .filter(|word| word.as_str() != BOUNDARY)
+            .count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+fn multipliers(sources: &[Source], totals: &[usize]) -> Vec<u32> {
+    /*
+        A function to turn source weights into whole-number count multipliers. The share
+        of a word in a source is its weight divided by its size; shares are scaled so the
+        smallest becomes WEIGHT_RESOLUTION, then divided by their common divisor to keep
+        the counts small.
+    */
+    let shares = sources
+        .iter()
+        .zip(totals)
+        .map(|(source, &total)| match source.weight {
+            Some(weight) if total > 0 => weight / total as f64,
+            Some(_) => 0.0,
+            None => 1.0,
+        })
+        .collect::<Vec<f64>>();
+    let smallest = shares
+        .iter()
+        .copied()
+        .filter(|&share| share > 0.0)
+        .fold(f64::INFINITY, f64::min);
+    if sources.iter().all(|source| source.weight.is_none()) {
+        return vec![1; sources.len()];
+    }
+    if !smallest.is_finite() {
+        return vec![0; sources.len()];
+    }
+    let multipliers = shares
+        .iter()
+        .map(|share| (share / smallest * WEIGHT_RESOLUTION).round() as u32)
+        .collect::<Vec<u32>>();
+    let divisor = multipliers.iter().copied().fold(0, gcd).max(1);
+    multipliers
+        .into_iter()
+        .map(|multiplier| multiplier / divisor)
+        .collect()
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    match b {
+        0 => a,
+        b => gcd(b, a % b),
+    }
+}
+
+impl Default for Corpus {
+    fn default() -> Corpus {
+        Corpus::new()
+    }
+}
@@ -0,0 +1,124 @@
+/*
+    A weighted choice between distinct outcomes, such as the successors of a context.
+*/
+
+use crate::entropy::{shannon_entropy, Entropy, SamplingMode};
+use rand::{CryptoRng, Rng};
+
+#[derive(Clone, Debug)]
+pub struct Distribution<T> {
+    items: Vec<T>,
+    counts: Vec<u32>,
+    /* cumulative[i] is the sum of counts[..=i], so a draw is a binary search. */
+    cumulative: Vec<u64>,
+    /* The Shannon entropy of a frequency-weighted draw. */
+    weighted: f64,
+}
+
+impl<T: Ord> Distribution<T> {
+    pub(crate) fn from_counts<I: IntoIterator<Item = (T, u32)>>(counts: I) -> Distribution<T> {
+        /*
+            Outcomes are sorted so that the same counts always give the same draws
+            for a seeded RNG. Zero counts are dropped.
+        */
+        let mut pairs = counts
+            .into_iter()
+            .filter(|(_, count)| *count > 0)
+            .collect::<Vec<(T, u32)>>();
+        pairs.sort_by(|a, b| a.0.cmp(&b.0));
+        let (items, counts): (Vec<T>, Vec<u32>) = pairs.into_iter().unzip();
+        let cumulative = counts
+            .iter()
+            .scan(0u64, |total, &count| {
+                *total += count as u64;
+                Some(*total)
+            })
+            .collect();
+        let weighted = shannon_entropy(counts.iter().map(|&count| count as u64));
+        Distribution {
+            items,
+            counts,
+            cumulative,
+            weighted,
+        }
+    }
+
+    pub fn probability(&self, item: &T, mode: SamplingMode) -> f64 {
+        /*
+            The probability of drawing an outcome, zero if it is not one.
+        */
+        match self.items.binary_search(item) {
+            Ok(i) => match mode {
+                SamplingMode::Weighted => self.counts[i] as f64 / self.total() as f64,
+                SamplingMode::Uniform => 1.0 / self.len() as f64,
+            },
+            Err(_) => 0.0,
+        }
+    }
+
+    pub(crate) fn drawn(&self, item: &T, mode: SamplingMode) -> Entropy {
+        /*
+            The entropy of a draw that picked the given outcome.
+        */
+        Entropy::drawn(self.entropy(mode), self.probability(item, mode))
+    }
+}
+
+impl<T> Distribution<T> {
+    pub fn len(&self) -> usize {
+        self.items.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.cumulative.last().copied().unwrap_or(0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&T, u32)> {
+        self.items.iter().zip(self.counts.iter().copied())
+    }
+
+    pub fn probabilities(&self, mode: SamplingMode) -> impl Iterator<Item = (&T, f64)> {
+        /*
+            The probability of drawing each outcome under the given sampling mode.
+        */
+        let total = self.total() as f64;
+        let len = self.len() as f64;
+        self.iter().map(move |(item, count)| match mode {
+            SamplingMode::Weighted => (item, count as f64 / total),
+            SamplingMode::Uniform => (item, 1.0 / len),
+        })
+    }
+
+    pub fn entropy(&self, mode: SamplingMode) -> f64 {
+        /*
+            The Shannon entropy of one draw. A weighted draw falls short of log2 of the
+            number of outcomes whenever some outcomes are more frequent than others.
+        */
+        match mode {
+            SamplingMode::Weighted => self.weighted,
+            SamplingMode::Uniform => (self.len().max(1) as f64).log2(),
+        }
+    }
+
+    pub(crate) fn sample<R: Rng + CryptoRng + ?Sized>(
+        &self,
+        rng: &mut R,
+        mode: SamplingMode,
+    ) -> &T {
+        /*
+            Draws an outcome, either with probability proportional to its count or
+            uniformly among the distinct outcomes.
+        */
+        match mode {
+            SamplingMode::Weighted => {
+                let point = rng.gen_range(0..self.total());
+                &self.items[self.cumulative.partition_point(|&total| total <= point)]
+            }
+            SamplingMode::Uniform => &self.items[rng.gen_range(0..self.len())],
+        }
+    }
+}
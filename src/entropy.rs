@@ -0,0 +1,116 @@
+/*
+    Entropy accounting for the choices made while generating a passphrase.
+*/
+
+use std::iter::Sum;
+use std::ops::{Add, AddAssign};
+
+pub fn shannon_entropy<I: IntoIterator<Item = u64>>(counts: I) -> f64 {
+    /*
+        A function to compute the Shannon entropy, in bits, of a draw where each
+        outcome is weighted by its count. Outcomes must already be distinct: the
+        same word counted twice would look like two separate choices.
+    */
+    let counts = counts.into_iter().collect::<Vec<u64>>();
+    let total = counts.iter().sum::<u64>() as f64;
+    counts
+        .into_iter()
+        .filter(|&c| c > 0)
+        .map(|c| {
+            let p = c as f64 / total;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Entropy {
+    /* The average number of bits per passphrase produced this way. */
+    pub shannon_bits: f64,
+    /*
+        The surprisal of the outcomes actually drawn, -log2 of their probability. An
+        attacker who guesses the most likely passphrases first reaches one with little
+        surprisal early, whatever the average.
+    */
+    pub surprisal_bits: f64,
+}
+
+impl Entropy {
+    pub fn uniform(outcomes: usize) -> Entropy {
+        /*
+            A uniform choice between n outcomes carries log2(n) bits by either measure.
+        */
+        let bits = (outcomes.max(1) as f64).log2();
+        Entropy {
+            shannon_bits: bits,
+            surprisal_bits: bits,
+        }
+    }
+
+    pub(crate) fn drawn(shannon_bits: f64, probability: f64) -> Entropy {
+        /*
+            The entropy of a draw with the given Shannon entropy that picked an outcome
+            of the given probability. A certain draw carries 0 bits, where
+            -probability.log2() would give -0.
+        */
+        Entropy {
+            shannon_bits,
+            surprisal_bits: (1.0 / probability).log2(),
+        }
+    }
+
+    pub(crate) fn from_weights(weights: &[f64], index: usize) -> Entropy {
+        /*
+            The entropy of a draw where each outcome is weighted by a non-negative number,
+            for distributions that are not made of whole counts, that picked the outcome
+            at index.
+        */
+        let total = weights.iter().sum::<f64>();
+        if total <= 0.0 {
+            return Entropy::default();
+        }
+        Entropy::drawn(
+            weights
+                .iter()
+                .filter(|&&weight| weight > 0.0)
+                .map(|&weight| weight / total * (total / weight).log2())
+                .sum(),
+            weights[index] / total,
+        )
+    }
+}
+
+impl Add for Entropy {
+    type Output = Entropy;
+
+    fn add(self, other: Entropy) -> Entropy {
+        Entropy {
+            shannon_bits: self.shannon_bits + other.shannon_bits,
+            surprisal_bits: self.surprisal_bits + other.surprisal_bits,
+        }
+    }
+}
+
+impl AddAssign for Entropy {
+    fn add_assign(&mut self, other: Entropy) {
+        *self = *self + other;
+    }
+}
+
+impl Sum for Entropy {
+    fn sum<I: Iterator<Item = Entropy>>(iter: I) -> Entropy {
+        iter.fold(Entropy::default(), Add::add)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SamplingMode {
+    /* Pick successors in proportion to how often they follow the context in the corpus. */
+    #[default]
+    Weighted,
+    /*
+        Pick uniformly among the distinct successors, ignoring frequency. The chain reads
+        less like the corpus, but frequent paths are no easier to guess than rare ones.
+    */
+    Uniform,
+}
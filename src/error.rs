@@ -0,0 +1,90 @@
+/*
+    The error type shared by the library and the command line interface.
+*/
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum PassphraseError {
+    /* The command line could not be understood. */
+    Usage(String),
+    /* The training data file could not be read. */
+    MissingCorpus { path: PathBuf, source: io::Error },
+    /* The training data has too few words to pick a start state. */
+    CorpusTooSmall { words: usize, required: usize },
+    /* The requested length or entropy target is not usable. */
+    InvalidLength(String),
+    /* The chain cannot produce a passphrase that meets the request. */
+    NoReachableStates(String),
+    /* The requested n-gram order is not supported or does not match the model. */
+    InvalidOrder(String),
+    /* A model file could not be read or written. */
+    ModelIo { path: PathBuf, source: io::Error },
+    /* A model file is corrupted, truncated or in an unsupported format. */
+    InvalidModel(String),
+    /* A password policy is malformed, cannot be read, or can never be met. */
+    InvalidPolicy(String),
+}
+
+impl PassphraseError {
+    pub fn exit_code(&self) -> i32 {
+        /*
+            Distinct exit codes so that scripts wrapping the binary can tell failures apart.
+        */
+        match self {
+            PassphraseError::Usage(_) => 2,
+            PassphraseError::MissingCorpus { .. } => 3,
+            PassphraseError::CorpusTooSmall { .. } => 4,
+            PassphraseError::InvalidLength(_) => 5,
+            PassphraseError::NoReachableStates(_) => 6,
+            PassphraseError::InvalidOrder(_) => 7,
+            PassphraseError::ModelIo { .. } => 8,
+            PassphraseError::InvalidModel(_) => 9,
+            PassphraseError::InvalidPolicy(_) => 10,
+        }
+    }
+}
+
+impl fmt::Display for PassphraseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PassphraseError::Usage(message) => write!(f, "{message}"),
+            PassphraseError::MissingCorpus { path, source } => write!(
+                f,
+                "could not read training data from {}: {source}",
+                path.display()
+            ),
+            PassphraseError::CorpusTooSmall { words, required } => write!(
+                f,
+                "the training data has {words} words, but at least {required} are needed"
+            ),
+            PassphraseError::InvalidLength(message) => write!(f, "{message}"),
+            PassphraseError::NoReachableStates(message) => write!(f, "{message}"),
+            PassphraseError::InvalidOrder(message) => write!(f, "{message}"),
+            PassphraseError::ModelIo { path, source } => {
+                write!(
+                    f,
+                    "could not access model file {}: {source}",
+                    path.display()
+                )
+            }
+            PassphraseError::InvalidModel(reason) => write!(f, "invalid model file: {reason}"),
+            PassphraseError::InvalidPolicy(reason) => {
+                write!(f, "invalid password policy: {reason}")
+            }
+        }
+    }
+}
+
+impl Error for PassphraseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PassphraseError::MissingCorpus { source, .. } => Some(source),
+            PassphraseError::ModelIo { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
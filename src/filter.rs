@@ -0,0 +1,83 @@
+/*
+    Dropping tokens that should not become passphrase words.
+*/
+
+use crate::tokenizer::BOUNDARY;
+use std::collections::{HashMap, HashSet};
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TokenFilter {
+    /* The shortest word kept, in characters. Empty tokens are always dropped. */
+    pub min_length: usize,
+    pub max_length: Option<usize>,
+    /* Words that are never kept, already normalized by the corpus tokenizer. */
+    pub stopwords: HashSet<String>,
+    /* The fewest times a word must occur in the whole corpus to be kept. */
+    pub min_frequency: u32,
+}
+
+impl Default for TokenFilter {
+    fn default() -> TokenFilter {
+        TokenFilter {
+            min_length: 1,
+            max_length: None,
+            stopwords: HashSet::new(),
+            min_frequency: 1,
+        }
+    }
+}
+
+impl TokenFilter {
+    fn keeps(&self, word: &str, frequencies: &HashMap<&str, u32>) -> bool {
+        if word == BOUNDARY {
+            return true;
+        }
+        let length = word.chars().count();
+        length > 0
+            && length >= self.min_length
+            && self.max_length.is_none_or(|max| length <= max)
+            && !self.stopwords.contains(word)
+            && frequencies.get(word).copied().unwrap_or(0) >= self.min_frequency
+    }
+
+    pub fn segments(&self, documents: &[Vec<String>]) -> Vec<Vec<String>> {
+        /*
+            A function to split documents into the runs of words the filter keeps.
+            A dropped token is a chain break: the words on either side of it never
+            become neighbours, so "hold — on" does not teach the chain "hold on".
+            BOUNDARY markers are always kept, so a segment only opens at a line start
+            when the dropped token ended a line.
+        */
+        self.document_segments(documents)
+            .into_iter()
+            .flatten()
+            .collect()
+    }
+
+    pub(crate) fn document_segments(&self, documents: &[Vec<String>]) -> Vec<Vec<Vec<String>>> {
+        /*
+            The segments of every document, kept apart by document.
+        */
+        let mut frequencies: HashMap<&str, u32> = HashMap::new();
+        for word in documents.iter().flatten() {
+            *frequencies.entry(word.as_str()).or_insert(0) += 1;
+        }
+        let mut document_segments = Vec::new();
+        for words in documents {
+            let mut segments = Vec::new();
+            let mut segment = Vec::new();
+            for word in words {
+                if self.keeps(word, &frequencies) {
+                    segment.push(word.clone());
+                } else if !segment.is_empty() {
+                    segments.push(std::mem::take(&mut segment));
+                }
+            }
+            if !segment.is_empty() {
+                segments.push(segment);
+            }
+            document_segments.push(segments);
+        }
+        document_segments
+    }
+}
@@ -0,0 +1,794 @@
+/*
+    The public passphrase generation API.
+*/
+
+use crate::blocklist::{Blocklist, Pruning};
+use crate::corpus::Corpus;
+use crate::entropy::{Entropy, SamplingMode};
+use crate::error::PassphraseError;
+use crate::include::Inclusion;
+use crate::invent::{placement_entropy, CharacterModel, InventedWords, Inventor};
+use crate::model::{TransitionModel, DEFAULT_ORDER};
+use crate::policy::Policy;
+use crate::rng::EntropySource;
+use crate::sampler::{markov_chain, min_entropy, Chain, ChainFailure, ChainSettings, CharLimit};
+use crate::start::StartStrategy;
+use crate::tokenizer::BOUNDARY;
+use crate::verbatim::VerbatimIndex;
+use rand::seq::index::sample;
+use rand::{CryptoRng, Rng};
+use std::cell::OnceCell;
+use std::collections::HashSet;
+use std::fmt;
+
+pub const MAX_CHAIN_WORDS: usize = 64;
+pub const MAX_ATTEMPTS: usize = 1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    /* Generate a fixed number of words. */
+    Words(usize),
+    /* Keep extending the chain until the passphrase carries at least this many Shannon bits. */
+    MinBits(f64),
+    /*
+        Keep extending the chain until the draws of the passphrase carry at least this
+        many bits of surprisal, so that no passphrase falls short however likely its
+        words are.
+    */
+    MinSurprisal(f64),
+}
+
+impl Target {
+    fn validate(&self) -> Result<(), PassphraseError> {
+        match self {
+            Target::Words(0) => Err(PassphraseError::InvalidLength(
+                "the passphrase length must be at least 1 word".to_string(),
+            )),
+            Target::MinBits(bits) | Target::MinSurprisal(bits)
+                if !(bits.is_finite() && *bits > 0.0) =>
+            {
+                Err(PassphraseError::InvalidLength(format!(
+                    "the entropy target must be a positive number of bits, got {bits}"
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn reached(&self, words: usize, entropy: Entropy) -> bool {
+        /*
+            A Shannon target is met on average, so with weighted sampling a passphrase
+            of likely words can meet it with far less surprisal; a surprisal target is
+            met by every passphrase. A draw of the boundary marker adds entropy but no
+            word, so an entropy target is only met once there is at least one word.
+        */
+        match self {
+            Target::Words(length) => words >= *length,
+            Target::MinBits(bits) => words > 0 && entropy.shannon_bits >= *bits,
+            Target::MinSurprisal(bits) => words > 0 && entropy.surprisal_bits >= *bits,
+        }
+    }
+
+    pub(crate) fn is_entropy(&self) -> bool {
+        /* Whether the passphrase length depends on the draws. */
+        !matches!(self, Target::Words(_))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeadEndPolicy {
+    /* Continue from a fresh random start state, paying for its entropy again. */
+    Restart,
+    /* Give up and report the context that has no successors. */
+    Fail,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Capitalization {
+    Lower,
+    Upper,
+    /* Capitalize the first letter of every word. */
+    Title,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Format {
+    pub separator: String,
+    pub capitalization: Capitalization,
+}
+
+impl Default for Format {
+    fn default() -> Format {
+        Format {
+            separator: " ".to_string(),
+            capitalization: Capitalization::Lower,
+        }
+    }
+}
+
+pub(crate) fn capitalize(word: &str, capitalization: Capitalization) -> String {
+    match capitalization {
+        Capitalization::Lower => word.to_lowercase(),
+        Capitalization::Upper => word.to_uppercase(),
+        Capitalization::Title => {
+            let mut chars = word.chars();
+            match chars.next() {
+                Some(first) => first.to_uppercase().chain(chars).collect(),
+                None => String::new(),
+            }
+        }
+    }
+}
+
+impl Format {
+    fn apply(&self, words: &[String]) -> String {
+        words
+            .iter()
+            .map(|word| capitalize(word, self.capitalization))
+            .collect::<Vec<String>>()
+            .join(&self.separator)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Passphrase {
+    /* The words picked by the chain, before formatting. */
+    pub words: Vec<String>,
+    /* The formatted passphrase. */
+    pub phrase: String,
+    /* The entropy of the start-state choice plus every step of the chain. */
+    pub entropy: Entropy,
+    /* The part of the entropy contributed by the first start-state choice. */
+    pub start_entropy: Entropy,
+    /* The part of the entropy contributed by invented words and where they were placed. */
+    pub invented_entropy: Entropy,
+    /* The part of the entropy contributed by the random choices of a password policy. */
+    pub policy_entropy: Entropy,
+    /*
+        How much less entropy the chain's draws had because of a character limit than
+        the same draws without it. Leaving out a likely word can raise the entropy of a
+        draw, so a step may also count negatively.
+    */
+    pub length_reduction: Entropy,
+}
+
+impl fmt::Display for Passphrase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.phrase)
+    }
+}
+
+enum Training {
+    Corpus(Corpus),
+    Model(Box<TransitionModel>),
+}
+
+pub struct PassphraseGeneratorBuilder<R> {
+    training: Training,
+    order: Option<usize>,
+    rng: R,
+    target: Target,
+    dead_end: DeadEndPolicy,
+    sampling: SamplingMode,
+    min_successors: Option<usize>,
+    max_verbatim: Option<usize>,
+    end_at_boundary: bool,
+    start: Option<StartStrategy>,
+    include: Option<String>,
+    invented: Option<InventedWords>,
+    policy: Option<Policy>,
+    max_chars: Option<usize>,
+    blocklist: Option<Blocklist>,
+    format: Format,
+}
+
+impl<R: Rng + CryptoRng> PassphraseGeneratorBuilder<R> {
+    pub fn order(mut self, order: usize) -> Self {
+        self.order = Some(order);
+        self
+    }
+
+    pub fn rng<S: Rng + CryptoRng>(self, rng: S) -> PassphraseGeneratorBuilder<S> {
+        PassphraseGeneratorBuilder {
+            training: self.training,
+            order: self.order,
+            rng,
+            target: self.target,
+            dead_end: self.dead_end,
+            sampling: self.sampling,
+            min_successors: self.min_successors,
+            max_verbatim: self.max_verbatim,
+            end_at_boundary: self.end_at_boundary,
+            start: self.start,
+            include: self.include,
+            invented: self.invented,
+            policy: self.policy,
+            max_chars: self.max_chars,
+            blocklist: self.blocklist,
+            format: self.format,
+        }
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn length(self, words: usize) -> Self {
+        self.target(Target::Words(words))
+    }
+
+    pub fn min_bits(self, bits: f64) -> Self {
+        self.target(Target::MinBits(bits))
+    }
+
+    pub fn min_surprisal(self, bits: f64) -> Self {
+        self.target(Target::MinSurprisal(bits))
+    }
+
+    pub fn dead_end(mut self, dead_end: DeadEndPolicy) -> Self {
+        self.dead_end = dead_end;
+        self
+    }
+
+    pub fn sampling(mut self, sampling: SamplingMode) -> Self {
+        self.sampling = sampling;
+        self
+    }
+
+    pub fn backoff(mut self, min_successors: usize) -> Self {
+        /*
+            Backs off to a lower-order context whenever the current one has fewer than
+            min_successors distinct successors, down to a context-free unigram draw.
+        */
+        self.min_successors = Some(min_successors);
+        self
+    }
+
+    pub fn max_verbatim(mut self, words: usize) -> Self {
+        /*
+            Rejects and regenerates passphrases that share a run of more than this many
+            consecutive words with the training text.
+        */
+        self.max_verbatim = Some(words);
+        self
+    }
+
+    pub fn end_at_boundary(mut self, enabled: bool) -> Self {
+        /*
+            Keeps extending the chain past the target until it reaches the end of a line
+            or sentence, so the passphrase may be longer than requested. The model must
+            have been trained with boundaries.
+        */
+        self.end_at_boundary = enabled;
+        self
+    }
+
+    pub fn start(mut self, start: StartStrategy) -> Self {
+        /*
+            Sets how the start state is picked. By default, models trained with
+            boundaries start at line starts and others at any position.
+        */
+        self.start = Some(start);
+        self
+    }
+
+    pub fn include(mut self, word: &str) -> Self {
+        /*
+            Requires the passphrase to contain a word. Every choice until it appears is
+            conditioned on the word staying reachable, which lowers the entropy; the
+            reported entropy is that of the conditioned choices.
+        */
+        self.include = Some(word.to_string());
+        self
+    }
+
+    pub fn invented(mut self, invented: InventedWords) -> Self {
+        /*
+            Adds words invented by a character-level chain trained on the vocabulary of
+            the word model, or makes the passphrase of invented words alone. Invented
+            words are never words of the training text.
+        */
+        self.invented = Some(invented);
+        self
+    }
+
+    pub fn policy(mut self, policy: Policy) -> Self {
+        /*
+            Formats the passphrase to meet a password policy instead of with the
+            separator and capitalization of the format.
+        */
+        self.policy = Some(policy);
+        self
+    }
+
+    pub fn max_chars(mut self, chars: usize) -> Self {
+        /*
+            Limits the formatted passphrase to this many characters. Every draw leaves
+            out the words that would not fit, and the entropy is that of the constrained
+            draws. Can be combined with an entropy target.
+        */
+        self.max_chars = Some(chars);
+        self
+    }
+
+    pub fn blocklist(mut self, blocklist: Blocklist) -> Self {
+        /*
+            Prunes the words the blocklist blocks from the model before generating, so
+            they are never drawn, and rejects invented words it blocks.
+        */
+        self.blocklist = Some(blocklist);
+        self
+    }
+
+    pub fn format(mut self, format: Format) -> Self {
+        self.format = format;
+        self
+    }
+
+    pub fn separator(mut self, separator: &str) -> Self {
+        self.format.separator = separator.to_string();
+        self
+    }
+
+    pub fn capitalization(mut self, capitalization: Capitalization) -> Self {
+        self.format.capitalization = capitalization;
+        self
+    }
+
+    pub fn build(self) -> Result<PassphraseGenerator<R>, PassphraseError> {
+        /*
+            Validates the settings and trains the transition model on the corpus,
+            unless the builder was given an already trained model.
+        */
+        self.target.validate()?;
+        if let Some(policy) = &self.policy {
+            policy.validate()?;
+        }
+        let mut model = match self.training {
+            Training::Corpus(corpus) => {
+                TransitionModel::train(&corpus, self.order.unwrap_or(DEFAULT_ORDER))?
+            }
+            Training::Model(model) => match self.order {
+                Some(order) if order != model.order() => {
+                    return Err(PassphraseError::InvalidOrder(format!(
+                        "the model has order {}, but order {order} was requested",
+                        model.order()
+                    )))
+                }
+                _ => *model,
+            },
+        };
+        let pruning = self
+            .blocklist
+            .as_ref()
+            .map(|blocklist| model.prune(blocklist))
+            .transpose()?;
+        let verbatim = match self.max_verbatim {
+            Some(limit) if limit <= model.order() && self.min_successors.is_none() => {
+                return Err(PassphraseError::NoReachableStates(format!(
+                    "a verbatim limit of {limit} words is unreachable: every {} consecutive words of an order {} chain come from the training text",
+                    model.order() + 1,
+                    model.order()
+                )))
+            }
+            Some(_) if model.documents().is_empty() => {
+                return Err(PassphraseError::InvalidModel(
+                    "the model does not include its training text, retrain it to use the verbatim check"
+                        .to_string(),
+                ))
+            }
+            Some(limit) => {
+                /*
+                    Boundary markers never appear in a passphrase, so the index is built
+                    over the words alone and a run can span the end of a line.
+                */
+                let boundary = model.boundary();
+                let documents = model
+                    .documents()
+                    .iter()
+                    .map(|words| {
+                        words
+                            .iter()
+                            .copied()
+                            .filter(|&word| Some(word) != boundary)
+                            .collect()
+                    })
+                    .collect::<Vec<_>>();
+                Some((limit, VerbatimIndex::new(&documents)))
+            }
+            None => None,
+        };
+        if self.min_successors.is_some() && !model.has_backoff() {
+            return Err(PassphraseError::InvalidModel(
+                "the model has no lower-order tables, retrain it to use backoff".to_string(),
+            ));
+        }
+        if self.end_at_boundary && model.boundary().is_none() {
+            return Err(PassphraseError::NoReachableStates(
+                "the chain cannot end at a boundary: the model was trained without the lines or sentences tokenizer setting"
+                    .to_string(),
+            ));
+        }
+        if self.include.is_some() && self.target.is_entropy() {
+            return Err(PassphraseError::InvalidLength(
+                "a required word needs a passphrase length in words, not an entropy target"
+                    .to_string(),
+            ));
+        }
+        let inventor = match &self.invented {
+            Some(invented) => {
+                invented.validate()?;
+                if invented.mix.is_none() && self.include.is_some() {
+                    return Err(PassphraseError::NoReachableStates(
+                        "a passphrase of invented words cannot include a word of the training text"
+                            .to_string(),
+                    ));
+                }
+                let words = model
+                    .vocabulary()
+                    .words()
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|&word| word != BOUNDARY);
+                let characters = CharacterModel::train(words, invented.order)?;
+                Some(Inventor::new(
+                    characters,
+                    invented,
+                    self.sampling,
+                    self.blocklist.clone(),
+                )?)
+            }
+            None => None,
+        };
+        let max_chars = match self.max_chars {
+            Some(_) if self.invented.is_some() || self.policy.is_some() => {
+                return Err(PassphraseError::InvalidLength(
+                    "a character limit cannot be combined with invented words or a password policy, which has its own maximum length"
+                        .to_string(),
+                ))
+            }
+            Some(_) if self.include.is_some() => {
+                return Err(PassphraseError::InvalidLength(
+                    "a character limit cannot be combined with a required word".to_string(),
+                ))
+            }
+            Some(limit) => {
+                let separator = self.format.separator.chars().count();
+                let shortest = match self.target {
+                    Target::Words(words) => (words - 1)
+                        .checked_mul(separator)
+                        .and_then(|separators| separators.checked_add(words))
+                        .ok_or_else(|| {
+                            PassphraseError::InvalidLength(format!(
+                                "a passphrase of {words} words cannot fit in {limit} characters"
+                            ))
+                        })?,
+                    Target::MinBits(_) | Target::MinSurprisal(_) => 1,
+                };
+                if limit < shortest {
+                    return Err(PassphraseError::InvalidLength(format!(
+                        "a passphrase of this length needs at least {shortest} characters, but the limit is {limit}"
+                    )));
+                }
+                Some(CharLimit { limit, separator })
+            }
+            None => None,
+        };
+        let start = self
+            .start
+            .unwrap_or_else(|| StartStrategy::default_for(&model));
+        let restart = match start {
+            StartStrategy::Word(_) => StartStrategy::default_for(&model),
+            _ => start.clone(),
+        };
+        Ok(PassphraseGenerator {
+            rng: self.rng,
+            chain: ChainSettings {
+                start: start.resolve(&model)?,
+                restart: restart.resolve(&model)?,
+                include: self
+                    .include
+                    .map(|word| Inclusion::new(&model, &word, self.min_successors.is_some()))
+                    .transpose()?,
+                target: self.target,
+                dead_end: self.dead_end,
+                sampling: self.sampling,
+                min_successors: self.min_successors,
+                end_at_boundary: self.end_at_boundary,
+                max_chars,
+            },
+            model,
+            start,
+            verbatim,
+            invented: inventor.zip(self.invented),
+            pruning,
+            policy: self.policy,
+            format: self.format,
+            min_entropy: OnceCell::new(),
+        })
+    }
+}
+
+pub struct PassphraseGenerator<R> {
+    model: TransitionModel,
+    rng: R,
+    chain: ChainSettings,
+    start: StartStrategy,
+    /* The longest run allowed to match the training text, with the index to check it. */
+    verbatim: Option<(usize, VerbatimIndex)>,
+    invented: Option<(Inventor, InventedWords)>,
+    /* What the blocklist removed from the model, if one was given. */
+    pruning: Option<Pruning>,
+    policy: Option<Policy>,
+    format: Format,
+    /* The min-entropy of the generator, worked out the first time it is asked for. */
+    min_entropy: OnceCell<Option<f64>>,
+}
+
+impl PassphraseGenerator<EntropySource> {
+    pub fn builder(corpus: Corpus) -> PassphraseGeneratorBuilder<EntropySource> {
+        /*
+            Starts a builder with the defaults: an order 2 model, the OS CSPRNG,
+            4 words, restarting at dead ends and lowercase words separated by spaces.
+        */
+        PassphraseGenerator::with_training(Training::Corpus(corpus))
+    }
+
+    pub fn from_model(model: TransitionModel) -> PassphraseGeneratorBuilder<EntropySource> {
+        /*
+            Starts a builder over a model that was trained earlier, for example one
+            loaded with TransitionModel::load.
+        */
+        PassphraseGenerator::with_training(Training::Model(Box::new(model)))
+    }
+
+    fn with_training(training: Training) -> PassphraseGeneratorBuilder<EntropySource> {
+        PassphraseGeneratorBuilder {
+            training,
+            order: None,
+            rng: EntropySource::default(),
+            target: Target::Words(4),
+            dead_end: DeadEndPolicy::Restart,
+            sampling: SamplingMode::Weighted,
+            min_successors: None,
+            max_verbatim: None,
+            end_at_boundary: false,
+            start: None,
+            include: None,
+            invented: None,
+            policy: None,
+            max_chars: None,
+            blocklist: None,
+            format: Format::default(),
+        }
+    }
+}
+
+impl<R: Rng + CryptoRng> PassphraseGenerator<R> {
+    pub fn model(&self) -> &TransitionModel {
+        &self.model
+    }
+
+    pub fn rng(&self) -> &R {
+        &self.rng
+    }
+
+    pub fn start_strategy(&self) -> &StartStrategy {
+        &self.start
+    }
+
+    pub fn policy(&self) -> Option<&Policy> {
+        self.policy.as_ref()
+    }
+
+    pub fn pruning(&self) -> Option<&Pruning> {
+        self.pruning.as_ref()
+    }
+
+    pub fn min_entropy(&self) -> Option<f64> {
+        /*
+            A function to compute the min-entropy of the passphrases this generator
+            produces, -log2 of the probability of the most likely one, see
+            sampler::min_entropy. Unlike the entropy of a passphrase it is the same for
+            every passphrase. Returns None where it is not worked out: for an entropy
+            target, a required word, a character limit, ending at a line boundary,
+            failing at dead ends, invented words, a password policy or a verbatim limit.
+        */
+        *self.min_entropy.get_or_init(|| {
+            match self.invented.is_some() || self.policy.is_some() || self.verbatim.is_some() {
+                true => None,
+                false => min_entropy(&self.model, &self.chain),
+            }
+        })
+    }
+
+    pub fn generate(&mut self) -> Result<Passphrase, PassphraseError> {
+        /*
+            A function to generate a passphrase and format it. With a password policy,
+            passphrases that break its length or character class rules are generated
+            again; like the verbatim check, this removes outcomes, so the reported
+            entropy is an upper bound whenever the policy rejects some.
+        */
+        let policy = match self.policy.clone() {
+            Some(policy) => policy,
+            None => return self.compose(),
+        };
+        for _ in 0..MAX_ATTEMPTS {
+            let mut passphrase = self.compose()?;
+            let (phrase, policy_entropy) = policy.apply(&mut self.rng, &passphrase.words);
+            if policy.accepts(&phrase) {
+                passphrase.phrase = phrase;
+                passphrase.entropy += policy_entropy;
+                passphrase.policy_entropy = policy_entropy;
+                return Ok(passphrase);
+            }
+        }
+        Err(PassphraseError::NoReachableStates(format!(
+            "could not meet the {} policy within {MAX_ATTEMPTS} attempts, try another length",
+            policy.name
+        )))
+    }
+
+    pub fn generate_batch(&mut self, count: usize) -> Result<Vec<Passphrase>, PassphraseError> {
+        /*
+            A function to generate `count` passphrases from the same model that are
+            pairwise distinct as formatted. A passphrase that repeats an earlier one is
+            generated again, so each still carries the entropy reported for it; only the
+            handful of outcomes already taken are excluded.
+        */
+        let mut seen = HashSet::new();
+        let mut batch = Vec::with_capacity(count);
+        while batch.len() < count {
+            let mut repeats = 0;
+            let passphrase = loop {
+                let passphrase = self.generate()?;
+                if seen.insert(passphrase.phrase.clone()) {
+                    break passphrase;
+                }
+                repeats += 1;
+                if repeats >= MAX_ATTEMPTS {
+                    return Err(PassphraseError::NoReachableStates(format!(
+                        "could only generate {} distinct passphrases, the model produces too few; try a longer passphrase",
+                        batch.len()
+                    )));
+                }
+            };
+            batch.push(passphrase);
+        }
+        Ok(batch)
+    }
+
+    fn compose(&mut self) -> Result<Passphrase, PassphraseError> {
+        /*
+            A function to generate a passphrase from the chain, from invented words, or
+            from both with the invented words placed at random among the others.
+        */
+        let (mut words, mut entropy, start_entropy, length_reduction) = match &self.invented {
+            Some((_, invented)) if invented.mix.is_none() => Default::default(),
+            _ => {
+                let (words, chain) = self.chain_words()?;
+                (
+                    words,
+                    chain.entropy,
+                    chain.start_entropy,
+                    chain.length_reduction,
+                )
+            }
+        };
+        let mut invented_entropy = Entropy::default();
+        if let Some((inventor, invented)) = &self.invented {
+            let target = self.chain.target;
+            let mut new_words = Vec::new();
+            while !match invented.mix {
+                Some(count) => new_words.len() >= count,
+                None => target.reached(new_words.len(), invented_entropy),
+            } {
+                if new_words.len() >= MAX_CHAIN_WORDS {
+                    return Err(PassphraseError::NoReachableStates(format!(
+                        "could not reach the entropy target within {MAX_CHAIN_WORDS} invented words"
+                    )));
+                }
+                let (word, word_entropy) = inventor.invent(&mut self.rng)?;
+                new_words.push(word);
+                invented_entropy += word_entropy;
+            }
+            if invented.mix.is_some() {
+                let total = words.len() + new_words.len();
+                invented_entropy += placement_entropy(total, new_words.len());
+                let mut slots = sample(&mut self.rng, total, new_words.len()).into_vec();
+                slots.sort_unstable();
+                for (slot, word) in slots.into_iter().zip(new_words) {
+                    words.insert(slot, word);
+                }
+            } else {
+                words = new_words;
+            }
+            entropy += invented_entropy;
+        }
+        Ok(Passphrase {
+            phrase: self.format.apply(&words),
+            words,
+            entropy,
+            start_entropy,
+            invented_entropy,
+            policy_entropy: Entropy::default(),
+            length_reduction,
+        })
+    }
+
+    fn chain_words(&mut self) -> Result<(Vec<String>, Chain), PassphraseError> {
+        /*
+            A function to generate a passphrase using a Markov chain.
+            The reported entropy covers both the start-state choice and every step of the chain.
+            With an entropy target, chains that grow too long without reaching it are discarded
+            and regenerated from scratch, and so are chains that copy too long a run of the
+            training text. Those rejections remove outcomes, so the reported entropy is an
+            upper bound whenever a verbatim limit is set.
+        */
+        let mut verbatim_rejections = 0;
+        for _ in 0..MAX_ATTEMPTS {
+            match markov_chain(&mut self.rng, &self.model, &self.chain) {
+                Ok(chain)
+                    if self
+                        .verbatim
+                        .as_ref()
+                        .is_some_and(|(limit, index)| index.longest_run(&chain.words) > *limit) =>
+                {
+                    verbatim_rejections += 1;
+                }
+                Ok(chain) => {
+                    let vocabulary = self.model.vocabulary();
+                    let words = chain
+                        .words
+                        .iter()
+                        .map(|&id| vocabulary.word(id).to_string())
+                        .collect::<Vec<String>>();
+                    /* Capitalization can change the length of a word, as "ß" does. */
+                    if self.chain.max_chars.is_some_and(|max_chars| {
+                        self.format.apply(&words).chars().count() > max_chars.limit
+                    }) {
+                        continue;
+                    }
+                    return Ok((words, chain));
+                }
+                Err(ChainFailure::TooLong) | Err(ChainFailure::OverLength) => continue,
+                Err(ChainFailure::Unreachable) => {
+                    let word = self
+                        .chain
+                        .include
+                        .as_ref()
+                        .map_or("", |include| self.model.vocabulary().word(include.word()));
+                    return Err(PassphraseError::NoReachableStates(format!(
+                        "no passphrase from the training data can include \"{word}\" within the requested length"
+                    )));
+                }
+                Err(ChainFailure::DeadEnd(context)) => {
+                    return Err(PassphraseError::NoReachableStates(format!(
+                        "the chain reached \"{}\", which has no successors in the training data",
+                        self.model.describe(&context)
+                    )))
+                }
+            }
+        }
+        if verbatim_rejections > 0 {
+            return Err(PassphraseError::NoReachableStates(format!(
+                "could not avoid copying the training text within {MAX_ATTEMPTS} attempts ({verbatim_rejections} were too close to it)"
+            )));
+        }
+        if let Some(max_chars) = self.chain.max_chars {
+            return Err(PassphraseError::NoReachableStates(format!(
+                "could not fit a passphrase within {} characters in {MAX_ATTEMPTS} attempts",
+                max_chars.limit
+            )));
+        }
+        if self.chain.end_at_boundary {
+            return Err(PassphraseError::NoReachableStates(format!(
+                "could not end at a boundary within {MAX_ATTEMPTS} attempts of up to {MAX_CHAIN_WORDS} words"
+            )));
+        }
+        Err(PassphraseError::NoReachableStates(format!(
+            "could not reach the entropy target within {MAX_ATTEMPTS} attempts of up to {MAX_CHAIN_WORDS} words"
+        )))
+    }
+}
@@ -0,0 +1,628 @@
+/*
+    Constraining the chain to include a given word.
+
+    Rejecting chains until one happens to contain the word would waste most attempts on
+    rare words, and would leave the entropy of what is accepted unknown. Instead, every
+    choice is conditioned on the word still being reachable: an outcome is drawn with
+    probability proportional to its probability in the model times the probability of
+    reaching the word from where it leads. The conditioned draws are exactly the chains
+    the model produces that contain the word, and their entropy is what is reported.
+*/
+
+use crate::distribution::Distribution;
+use crate::entropy::Entropy;
+use crate::error::PassphraseError;
+use crate::generator::DeadEndPolicy;
+use crate::model::{Context, TransitionModel};
+use crate::sampler::{next_words, ChainSettings};
+use crate::start::word_id;
+use crate::vocabulary::WordId;
+use rand::{CryptoRng, Rng};
+use std::cell::{OnceCell, RefCell};
+use std::collections::HashMap;
+
+pub(crate) struct Inclusion {
+    word: WordId,
+    /*
+        With backoff, a context the model has never seen behaves exactly like its longest
+        seen suffix, so contexts are reduced to that suffix before they are looked up.
+    */
+    backoff: bool,
+    /*
+        For every seen context shorter than the order, the words that extend it into a
+        longer seen context. Only these words make a longer context differ from its suffix.
+    */
+    extensions: HashMap<Context, Vec<WordId>>,
+    /* The contexts the chain can reach and how their probabilities add up, see Graph. */
+    graph: OnceCell<Graph>,
+    /* The probability of reaching the word from every context, by words left, see Layers. */
+    layers: RefCell<Layers>,
+}
+
+/* Where a draw leads: to the word itself, or to a numbered context. */
+#[derive(Clone, Copy)]
+enum Target {
+    Word,
+    /*
+        The end of a line, or an order 0 start state, uses no word, so it leads to the
+        context with as many words left.
+    */
+    Context { state: usize, uses_word: bool },
+}
+
+impl Target {
+    fn reach(&self, previous: &[f64], layer: &[f64]) -> f64 {
+        /* The probability of reaching the word from here, given the layers around it. */
+        match *self {
+            Target::Word => 1.0,
+            Target::Context {
+                state,
+                uses_word: true,
+            } => previous[state],
+            Target::Context { state, .. } => layer[state],
+        }
+    }
+}
+
+fn context_after(model: &TransitionModel, before: &Context, next_word: WordId) -> Context {
+    /* The context after next_word is drawn after the words `before`. */
+    let mut context = Context::default();
+    if model.order() > 0 {
+        context = Context::new([before.words(), &[next_word]].concat());
+    }
+    context.mark_boundary(model.boundary());
+    context
+}
+
+/* How the probability of reaching the word from a context adds up. */
+enum Node {
+    /* Over the distribution the next word is drawn from, as one of the sums. */
+    Sum(usize),
+    /* At a dead end, over the start states after a restart. */
+    Restart,
+    /* At a dead end that fails, the word is never reached. */
+    DeadEnd,
+    /* At the start of a line, see LineStart. */
+    LineStart,
+}
+
+struct Sum {
+    /* The sum after the shorter context this one corrects, see Builder::sum. */
+    base: Option<usize>,
+    terms: Vec<(f64, Target)>,
+}
+
+/*
+    The context at the start of a line can end the line again, which leads straight back
+    to it without using a word. Its probability x is the solution of x = rest + repeat * x,
+    where rest sums over the words it can emit, so x = rest / (1 - repeat).
+*/
+struct LineStart {
+    state: usize,
+    terms: Vec<(f64, Target)>,
+    repeat: f64,
+}
+
+/*
+    The contexts the chain can reach, reduced to their longest seen suffix and numbered,
+    with the sums their probabilities are computed from. It is built once; the sums are
+    then evaluated for every number of words left, see Layers.
+*/
+struct Graph {
+    states: HashMap<Context, usize>,
+    nodes: Vec<Node>,
+    /* Every sum comes after its base. */
+    sums: Vec<Sum>,
+    restart: Vec<(f64, Target)>,
+    line_start: Option<LineStart>,
+}
+
+impl Graph {
+    fn state(&self, context: &Context) -> usize {
+        self.states[context]
+    }
+
+    fn layer(&self, previous: &[f64]) -> Vec<f64> {
+        /*
+            A function to compute the probability of reaching the word from every context
+            with k words left, from the probabilities with k - 1 words left. Only the end
+            of a line looks up the same layer, and it always leads to the line-start
+            context, so that one is solved first.
+        */
+        let mut layer = vec![0.0; self.nodes.len()];
+        let total = |layer: &[f64], terms: &[(f64, Target)]| {
+            terms
+                .iter()
+                .map(|(probability, target)| probability * target.reach(previous, layer))
+                .sum::<f64>()
+        };
+        if let Some(line_start) = &self.line_start {
+            let rest = total(&layer, &line_start.terms);
+            layer[line_start.state] = match line_start.repeat < 1.0 {
+                true => rest / (1.0 - line_start.repeat),
+                false => 0.0,
+            };
+        }
+        let mut sums = Vec::with_capacity(self.sums.len());
+        for sum in &self.sums {
+            let base = sum.base.map_or(0.0, |base| sums[base]);
+            sums.push(base + total(&layer, &sum.terms));
+        }
+        for (state, node) in self.nodes.iter().enumerate() {
+            if let Node::Sum(sum) = node {
+                layer[state] = sums[*sum];
+            }
+        }
+        /*
+            A restart only looks up the same layer for an order 0 start state, which is
+            never a dead end itself.
+        */
+        let restart = total(&layer, &self.restart);
+        for (state, node) in self.nodes.iter().enumerate() {
+            if let Node::Restart = node {
+                layer[state] = restart;
+            }
+        }
+        layer
+    }
+}
+
+/*
+    The probabilities of reaching the word, one vector over the numbered contexts for
+    every number of words left. A long passphrase needs thousands of them, so only every
+    spacing-th layer is kept, along with the block above the last one asked for, which is
+    computed again from the kept layer below it when needed. The chain asks for fewer
+    words left at every step, so each block is computed once per passphrase.
+*/
+#[derive(Default)]
+struct Layers {
+    spacing: usize,
+    /* The layers 0, spacing, 2 * spacing, and so on. */
+    kept: Vec<Vec<f64>>,
+    /* The layers from first to first + spacing. */
+    first: usize,
+    block: Vec<Vec<f64>>,
+}
+
+impl Layers {
+    fn around(&mut self, graph: &Graph, words: usize) -> (&[f64], &[f64]) {
+        /* The layers with words - 1 and words words left. */
+        if self.kept.is_empty() {
+            self.spacing = (words as f64).sqrt().ceil().max(1.0) as usize;
+            self.kept.push(vec![0.0; graph.nodes.len()]);
+        }
+        let index = (words - 1) / self.spacing;
+        while self.kept.len() <= index {
+            let mut layer = self.kept[self.kept.len() - 1].clone();
+            for _ in 0..self.spacing {
+                layer = graph.layer(&layer);
+            }
+            self.kept.push(layer);
+        }
+        if self.block.is_empty() || self.first != index * self.spacing {
+            self.first = index * self.spacing;
+            self.block = vec![self.kept[index].clone()];
+            for k in 0..self.spacing {
+                let layer = graph.layer(&self.block[k]);
+                self.block.push(layer);
+            }
+        }
+        let offset = words - self.first;
+        (&self.block[offset - 1], &self.block[offset])
+    }
+}
+
+/* Builds the graph of the contexts the chain can reach from its start states. */
+struct Builder<'m> {
+    inclusion: &'m Inclusion,
+    model: &'m TransitionModel,
+    settings: &'m ChainSettings,
+    graph: Graph,
+    /* The contexts numbered but not yet given a node. */
+    pending: Vec<(usize, Context)>,
+    /* The sums by the address of their distribution and the words before the outcome. */
+    sums: HashMap<(usize, Context), usize>,
+}
+
+impl<'m> Builder<'m> {
+    fn state(&mut self, context: &Context) -> usize {
+        let context = self.inclusion.seen(self.model, context);
+        if let Some(&state) = self.graph.states.get(&context) {
+            return state;
+        }
+        let state = self.graph.nodes.len();
+        self.graph.states.insert(context.clone(), state);
+        self.graph.nodes.push(Node::DeadEnd);
+        self.pending.push((state, context));
+        state
+    }
+
+    fn target(&mut self, before: &Context, next_word: WordId) -> Target {
+        /*
+            Where drawing next_word after the words `before` leads.
+        */
+        if next_word == self.inclusion.word {
+            return Target::Word;
+        }
+        Target::Context {
+            state: self.state(&context_after(self.model, before, next_word)),
+            uses_word: self.model.boundary() != Some(next_word),
+        }
+    }
+
+    fn start(&mut self, context: &Context, first: Option<WordId>) -> Target {
+        /*
+            Where a start state leads: it uses a word when it opens with one.
+        */
+        match first {
+            Some(first) if first == self.inclusion.word => Target::Word,
+            first => Target::Context {
+                state: self.state(context),
+                uses_word: first.is_some(),
+            },
+        }
+    }
+
+    fn sum(&mut self, next_words: &'m Distribution<WordId>, before: &Context) -> usize {
+        /*
+            A function to sum the probability of reaching the word over the outcomes of
+            a distribution drawn after the words `before`. With backoff, a large
+            distribution is summed once after the shorter suffix of `before`, and then
+            corrected for the few outcomes that extend `before` into a seen context, and
+            for the end of a line, which empties the context whatever came before.
+        */
+        let key = (next_words as *const _ as usize, before.clone());
+        if let Some(&sum) = self.sums.get(&key) {
+            return sum;
+        }
+        let extensions = self
+            .inclusion
+            .extensions
+            .get(before)
+            .map_or(&[][..], Vec::as_slice);
+        let sampling = self.settings.sampling;
+        let sum = if self.inclusion.backoff
+            && !before.words().is_empty()
+            && extensions.len() < next_words.len()
+        {
+            let shorter = before.suffix(before.words().len() - 1);
+            let boundary = self
+                .model
+                .boundary()
+                .filter(|boundary| !extensions.contains(boundary));
+            let exceptions = extensions
+                .iter()
+                .chain(boundary.as_slice())
+                .copied()
+                .collect::<Vec<WordId>>();
+            let base = self.sum(next_words, &shorter);
+            let mut terms = Vec::new();
+            for next_word in exceptions {
+                let probability = next_words.probability(&next_word, sampling);
+                if probability > 0.0 {
+                    terms.push((probability, self.target(before, next_word)));
+                    terms.push((-probability, self.target(&shorter, next_word)));
+                }
+            }
+            Sum {
+                base: Some(base),
+                terms,
+            }
+        } else {
+            let terms = next_words
+                .probabilities(sampling)
+                .map(|(&next_word, probability)| (probability, self.target(before, next_word)))
+                .collect();
+            Sum { base: None, terms }
+        };
+        self.graph.sums.push(sum);
+        let sum = self.graph.sums.len() - 1;
+        self.sums.insert(key, sum);
+        sum
+    }
+
+    fn build(mut self) -> Graph {
+        let (model, settings) = (self.model, self.settings);
+        for (context, _, _) in settings.start.outcomes() {
+            self.state(context);
+        }
+        self.graph.restart = settings
+            .restart
+            .outcomes()
+            .map(|(context, first, probability)| (probability, self.start(context, first)))
+            .collect();
+        if let Some(boundary) = model.boundary() {
+            let context = Context::new(vec![boundary; model.order()]);
+            let state = self.state(&context);
+            let context = self.inclusion.seen(model, &context);
+            if let Some(next_words) = next_words(model, &context, settings.min_successors) {
+                let before = context.suffix(model.order().saturating_sub(1));
+                let terms = next_words
+                    .probabilities(settings.sampling)
+                    .filter(|&(&next_word, _)| next_word != boundary)
+                    .map(|(&next_word, probability)| (probability, self.target(&before, next_word)))
+                    .collect();
+                self.graph.line_start = Some(LineStart {
+                    state,
+                    terms,
+                    repeat: next_words.probability(&boundary, settings.sampling),
+                });
+            }
+        }
+        while let Some((state, context)) = self.pending.pop() {
+            self.graph.nodes[state] = match next_words(model, &context, settings.min_successors) {
+                Some(next_words) => {
+                    let before = context.suffix(model.order().saturating_sub(1));
+                    Node::Sum(self.sum(next_words, &before))
+                }
+                None if settings.dead_end == DeadEndPolicy::Restart => Node::Restart,
+                None => Node::DeadEnd,
+            };
+        }
+        if let Some(line_start) = &self.graph.line_start {
+            self.graph.nodes[line_start.state] = Node::LineStart;
+        }
+        self.graph
+    }
+}
+
+impl Inclusion {
+    pub(crate) fn new(
+        model: &TransitionModel,
+        word: &str,
+        backoff: bool,
+    ) -> Result<Inclusion, PassphraseError> {
+        let mut extensions: HashMap<Context, Vec<WordId>> = HashMap::new();
+        if backoff {
+            let tables = model.backoff.iter().skip(1).chain([&model.transitions]);
+            for context in tables.flat_map(HashMap::keys) {
+                let words = context.words();
+                extensions
+                    .entry(Context::new(words[..words.len() - 1].to_vec()))
+                    .or_default()
+                    .push(words[words.len() - 1]);
+            }
+        }
+        Ok(Inclusion {
+            word: word_id(model, word)?,
+            backoff,
+            extensions,
+            graph: OnceCell::new(),
+            layers: RefCell::new(Layers::default()),
+        })
+    }
+
+    pub(crate) fn word(&self) -> WordId {
+        self.word
+    }
+
+    fn seen(&self, model: &TransitionModel, context: &Context) -> Context {
+        /*
+            The longest suffix of a context that the model has seen, or the context
+            itself without backoff, where an unseen context is a dead end.
+        */
+        if !self.backoff {
+            return context.clone();
+        }
+        (1..=context.words().len())
+            .rev()
+            .map(|k| context.suffix(k))
+            .find(|suffix| model.successors(suffix).is_some())
+            .unwrap_or_default()
+    }
+
+    fn reach<T>(
+        &self,
+        model: &TransitionModel,
+        settings: &ChainSettings,
+        words: usize,
+        f: impl FnOnce(&Graph, &dyn Fn(&Target) -> f64) -> T,
+    ) -> T {
+        /*
+            Calls f with the probability of reaching the word from a target, with `words`
+            words left including the one that leads to it.
+        */
+        let graph = self.graph.get_or_init(|| {
+            Builder {
+                inclusion: self,
+                model,
+                settings,
+                graph: Graph {
+                    states: HashMap::new(),
+                    nodes: Vec::new(),
+                    sums: Vec::new(),
+                    restart: Vec::new(),
+                    line_start: None,
+                },
+                pending: Vec::new(),
+                sums: HashMap::new(),
+            }
+            .build()
+        });
+        let mut layers = self.layers.borrow_mut();
+        let (previous, layer) = layers.around(graph, words);
+        f(graph, &|target| target.reach(previous, layer))
+    }
+
+    pub(crate) fn after_start(
+        &self,
+        model: &TransitionModel,
+        settings: &ChainSettings,
+        context: &Context,
+        first: Option<WordId>,
+        words: usize,
+    ) -> f64 {
+        /*
+            The probability of reaching the word from a start state, with `words` words
+            left including the one it opens with.
+        */
+        let context = self.seen(model, context);
+        self.reach(model, settings, words, |graph, reach| {
+            reach(&match first {
+                Some(first) if first == self.word => Target::Word,
+                first => Target::Context {
+                    state: graph.state(&context),
+                    uses_word: first.is_some(),
+                },
+            })
+        })
+    }
+
+    pub(crate) fn step_weights(
+        &self,
+        model: &TransitionModel,
+        settings: &ChainSettings,
+        context: &Context,
+        next_words: &[(WordId, f64)],
+        words: usize,
+    ) -> Vec<f64> {
+        /*
+            The probability of every next word times the probability of reaching the
+            word after it, with `words` words left including the next one.
+        */
+        let before = self
+            .seen(model, context)
+            .suffix(model.order().saturating_sub(1));
+        self.reach(model, settings, words, |graph, reach| {
+            next_words
+                .iter()
+                .map(|&(next_word, probability)| {
+                    let target = match next_word == self.word {
+                        true => Target::Word,
+                        false => Target::Context {
+                            state: graph.state(
+                                &self.seen(model, &context_after(model, &before, next_word)),
+                            ),
+                            uses_word: model.boundary() != Some(next_word),
+                        },
+                    };
+                    probability * reach(&target)
+                })
+                .collect()
+        })
+    }
+}
+
+pub(crate) fn conditioned<R: Rng + CryptoRng + ?Sized>(
+    rng: &mut R,
+    weights: &[f64],
+) -> Option<(usize, Entropy)> {
+    /*
+        A function to draw an index with probability proportional to its weight,
+        returning it with the entropy of the draw, or None if every weight is zero.
+    */
+    let total = weights.iter().sum::<f64>();
+    if total <= 0.0 {
+        return None;
+    }
+    let point = rng.gen_range(0.0..total);
+    let mut cumulative = 0.0;
+    let mut index = weights.iter().rposition(|&weight| weight > 0.0)?;
+    for (i, &weight) in weights.iter().enumerate() {
+        cumulative += weight;
+        if weight > 0.0 && point < cumulative {
+            index = i;
+            break;
+        }
+    }
+    Some((index, Entropy::from_weights(weights, index)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::corpus::Corpus;
+    use crate::generator::{PassphraseGenerator, PassphraseGeneratorBuilder};
+    use crate::rng::EntropySource;
+    use std::collections::HashMap;
+
+    const TEXT: &str = "the cat sat on the mat\nthe dog ran to the cat\na dog sat by a log\n";
+    const WORD: &str = "log";
+    const DRAWS: usize = 3000;
+
+    fn builder(
+        tokenizer: &str,
+        backoff: bool,
+        seed: u64,
+    ) -> PassphraseGeneratorBuilder<EntropySource> {
+        let mut corpus = Corpus::with_tokenizer(tokenizer.parse().unwrap());
+        corpus.add_text(TEXT);
+        let builder = PassphraseGenerator::builder(corpus)
+            .order(1)
+            .rng(EntropySource::from_seed(Some(seed)))
+            .length(3);
+        match backoff {
+            true => builder.backoff(2),
+            false => builder,
+        }
+    }
+
+    fn counts(generator: &mut PassphraseGenerator<EntropySource>) -> HashMap<String, usize> {
+        /*
+            A function to draw passphrases until DRAWS of them contain the word, and
+            count how often each of those occurred.
+        */
+        let mut counts = HashMap::new();
+        let mut kept = 0;
+        while kept < DRAWS {
+            let passphrase = generator.generate().unwrap();
+            if passphrase.words.iter().any(|word| word == WORD) {
+                *counts.entry(passphrase.phrase).or_default() += 1;
+                kept += 1;
+            }
+        }
+        counts
+    }
+
+    fn check(tokenizer: &str, backoff: bool) {
+        /*
+            Conditioning the draws on the word must give the same passphrases, with the
+            same probabilities, as keeping the unconstrained passphrases that contain
+            it. The two samples are compared with a chi-square test, which fails well
+            beyond what chance explains: 5 standard deviations above the mean.
+        */
+        let conditioned = counts(
+            &mut builder(tokenizer, backoff, 1)
+                .include(WORD)
+                .build()
+                .unwrap(),
+        );
+        let filtered = counts(&mut builder(tokenizer, backoff, 2).build().unwrap());
+        let mut phrases = conditioned
+            .keys()
+            .chain(filtered.keys())
+            .collect::<Vec<&String>>();
+        phrases.sort_unstable();
+        phrases.dedup();
+        let statistic = phrases
+            .iter()
+            .map(|&phrase| {
+                let a = conditioned.get(phrase).copied().unwrap_or(0) as f64;
+                let b = filtered.get(phrase).copied().unwrap_or(0) as f64;
+                (a - b).powi(2) / (a + b)
+            })
+            .sum::<f64>();
+        let freedom = (phrases.len() - 1) as f64;
+        let limit = freedom + 5.0 * (2.0 * freedom).sqrt();
+        assert!(
+            statistic < limit,
+            "{tokenizer}, backoff {backoff}: chi-square {statistic} over {} passphrases",
+            phrases.len()
+        );
+    }
+
+    #[test]
+    fn conditioned_draws_match_filtered_draws() {
+        check("ascii-alphabetic", false);
+    }
+
+    #[test]
+    fn conditioned_draws_match_filtered_draws_with_backoff() {
+        check("ascii-alphabetic", true);
+    }
+
+    #[test]
+    fn conditioned_draws_match_filtered_draws_with_lines() {
+        check("ascii-alphabetic,lines", false);
+        check("ascii-alphabetic,lines", true);
+    }
+}
@@ -0,0 +1,383 @@
+/*
+    Inventing pronounceable words with a character-level Markov chain.
+
+    The words of the training text are split into characters, and every character is
+    drawn given the few characters before it, so invented words follow the spelling
+    patterns of the corpus without being words of it. Like a required word, the length
+    limits and the ban on corpus words are met by conditioning every draw on them still
+    being reachable: a character is drawn with probability proportional to its
+    probability in the model times the probability that the chain goes on to end in
+    an allowed word. The reported entropy is that of the conditioned draws.
+    A blocklist matches patterns rather than a list of spellings, so an invented word
+    it blocks is rejected and invented again instead.
+*/
+
+use crate::blocklist::Blocklist;
+use crate::distribution::Distribution;
+use crate::entropy::{Entropy, SamplingMode};
+use crate::error::PassphraseError;
+use crate::generator::{MAX_ATTEMPTS, MAX_CHAIN_WORDS};
+use crate::include::conditioned;
+use rand::{CryptoRng, Rng};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+
+pub const DEFAULT_CHAR_ORDER: usize = 3;
+pub const MAX_CHAR_ORDER: usize = 8;
+pub const MAX_INVENTED_LENGTH: usize = 32;
+
+/*
+    The characters before the next one, with None for the positions before the start
+    of the word. Successors use None for the end of the word.
+*/
+type CharContext = Vec<Option<char>>;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct InventedWords {
+    /* The number of previous characters the next character depends on. */
+    pub order: usize,
+    /* The shortest and longest invented word, in characters. */
+    pub min_length: usize,
+    pub max_length: usize,
+    /*
+        With Some(n), n invented words are placed at random among the words of the
+        chain; with None, the passphrase is made of invented words alone.
+    */
+    pub mix: Option<usize>,
+}
+
+impl Default for InventedWords {
+    fn default() -> InventedWords {
+        InventedWords {
+            order: DEFAULT_CHAR_ORDER,
+            min_length: 5,
+            max_length: 9,
+            mix: None,
+        }
+    }
+}
+
+impl InventedWords {
+    pub(crate) fn validate(&self) -> Result<(), PassphraseError> {
+        if self.order > MAX_CHAR_ORDER {
+            return Err(PassphraseError::InvalidOrder(format!(
+                "a character order of {} is not supported, the maximum is {MAX_CHAR_ORDER}",
+                self.order
+            )));
+        }
+        if self.min_length == 0
+            || self.min_length > self.max_length
+            || self.max_length > MAX_INVENTED_LENGTH
+        {
+            return Err(PassphraseError::InvalidLength(format!(
+                "invented words must be between 1 and {MAX_INVENTED_LENGTH} characters long, got {} to {}",
+                self.min_length, self.max_length
+            )));
+        }
+        if let Some(count) = self.mix.filter(|&count| count > MAX_CHAIN_WORDS) {
+            return Err(PassphraseError::InvalidLength(format!(
+                "at most {MAX_CHAIN_WORDS} invented words can be mixed in, got {count}"
+            )));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct TrieNode {
+    children: BTreeMap<char, usize>,
+    /* Whether the characters leading here spell a corpus word. */
+    word: bool,
+}
+
+pub struct CharacterModel {
+    order: usize,
+    transitions: HashMap<CharContext, Distribution<Option<char>>>,
+    /* The corpus words, which are never invented, as a trie over their characters. */
+    trie: Vec<TrieNode>,
+}
+
+fn shifted(context: &CharContext, next: char) -> CharContext {
+    let mut context = context.clone();
+    if !context.is_empty() {
+        context.remove(0);
+        context.push(Some(next));
+    }
+    context
+}
+
+impl CharacterModel {
+    pub fn train<'a, I: IntoIterator<Item = &'a str>>(
+        words: I,
+        order: usize,
+    ) -> Result<CharacterModel, PassphraseError> {
+        /*
+            A function to train a character model on a list of distinct words, such as
+            the vocabulary of a word model. Every word counts once, however often it
+            occurs, so the model learns how words are spelled rather than which words
+            are common.
+        */
+        if order > MAX_CHAR_ORDER {
+            return Err(PassphraseError::InvalidOrder(format!(
+                "a character order of {order} is not supported, the maximum is {MAX_CHAR_ORDER}"
+            )));
+        }
+        let mut counts: HashMap<CharContext, HashMap<Option<char>, u32>> = HashMap::new();
+        let mut trie = vec![TrieNode::default()];
+        for word in words {
+            let mut context = vec![None; order];
+            let mut node = 0;
+            for next in word.chars().map(Some).chain([None]) {
+                *counts
+                    .entry(context.clone())
+                    .or_default()
+                    .entry(next)
+                    .or_insert(0) += 1;
+                if let Some(next) = next {
+                    context = shifted(&context, next);
+                    node = match trie[node].children.get(&next) {
+                        Some(&child) => child,
+                        None => {
+                            let child = trie.len();
+                            trie.push(TrieNode::default());
+                            trie[node].children.insert(next, child);
+                            child
+                        }
+                    };
+                }
+            }
+            trie[node].word = true;
+        }
+        if counts.is_empty() {
+            return Err(PassphraseError::CorpusTooSmall {
+                words: 0,
+                required: 1,
+            });
+        }
+        Ok(CharacterModel {
+            order,
+            transitions: counts
+                .into_iter()
+                .map(|(context, successors)| (context, Distribution::from_counts(successors)))
+                .collect(),
+            trie,
+        })
+    }
+
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    pub fn context_count(&self) -> usize {
+        self.transitions.len()
+    }
+}
+
+pub(crate) fn placement_entropy(words: usize, placed: usize) -> Entropy {
+    /*
+        The entropy of placing `placed` words at uniformly random positions among
+        `words` words in all, log2 of the binomial coefficient.
+    */
+    let bits = (0..placed)
+        .map(|i| ((words - i) as f64 / (i + 1) as f64).log2())
+        .sum();
+    Entropy {
+        shannon_bits: bits,
+        surprisal_bits: bits,
+    }
+}
+
+pub(crate) struct Inventor {
+    model: CharacterModel,
+    min_length: usize,
+    max_length: usize,
+    sampling: SamplingMode,
+    /* The probability of ending within the length limits, by (context, length so far). */
+    reach: RefCell<HashMap<(CharContext, usize), f64>>,
+    /*
+        For every node of the trie, the probability of going on to end exactly at a
+        corpus word within the length limits, which is subtracted from reach.
+    */
+    spelled: Vec<f64>,
+    blocklist: Option<Blocklist>,
+}
+
+impl Inventor {
+    pub(crate) fn new(
+        model: CharacterModel,
+        settings: &InventedWords,
+        sampling: SamplingMode,
+        blocklist: Option<Blocklist>,
+    ) -> Result<Inventor, PassphraseError> {
+        let mut inventor = Inventor {
+            min_length: settings.min_length,
+            max_length: settings.max_length,
+            sampling,
+            reach: RefCell::new(HashMap::new()),
+            spelled: vec![0.0; model.trie.len()],
+            model,
+            blocklist,
+        };
+        let start = vec![None; inventor.model.order];
+        inventor.spell(0, &start, 0);
+        if inventor.allowed(&start, 0, Some(0)) <= 0.0 {
+            return Err(PassphraseError::NoReachableStates(format!(
+                "the training data cannot form a new word of {} to {} characters",
+                settings.min_length, settings.max_length
+            )));
+        }
+        Ok(inventor)
+    }
+
+    fn probabilities(&self, context: &CharContext) -> Vec<(Option<char>, f64)> {
+        self.model
+            .transitions
+            .get(context)
+            .map(|next| {
+                next.probabilities(self.sampling)
+                    .map(|(&next, probability)| (next, probability))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn spell(&mut self, node: usize, context: &CharContext, length: usize) -> f64 {
+        /*
+            A function to fill in `spelled` for a node of the trie and every node below it.
+        */
+        let mut probability = 0.0;
+        for (next, p) in self.probabilities(context) {
+            probability += p * match next {
+                None if self.model.trie[node].word
+                    && (self.min_length..=self.max_length).contains(&length) =>
+                {
+                    1.0
+                }
+                None => 0.0,
+                Some(next) => match self.model.trie[node].children.get(&next) {
+                    Some(&child) if length < self.max_length => {
+                        self.spell(child, &shifted(context, next), length + 1)
+                    }
+                    _ => 0.0,
+                },
+            };
+        }
+        self.spelled[node] = probability;
+        probability
+    }
+
+    fn reach(&self, context: &CharContext, length: usize) -> f64 {
+        /*
+            A function to compute the probability that a word with `length` characters
+            so far, in the given context, ends within the length limits.
+        */
+        if length > self.max_length {
+            return 0.0;
+        }
+        let key = (context.clone(), length);
+        if let Some(&probability) = self.reach.borrow().get(&key) {
+            return probability;
+        }
+        let probability = self
+            .probabilities(context)
+            .into_iter()
+            .map(|(next, p)| {
+                p * match next {
+                    None if length >= self.min_length => 1.0,
+                    None => 0.0,
+                    Some(next) => self.reach(&shifted(context, next), length + 1),
+                }
+            })
+            .sum();
+        self.reach.borrow_mut().insert(key, probability);
+        probability
+    }
+
+    fn allowed(&self, context: &CharContext, length: usize, node: Option<usize>) -> f64 {
+        /*
+            The probability of ending within the length limits at a word that is not a
+            corpus word, from a trie node if the characters so far are a prefix of one.
+        */
+        let reach = self.reach(context, length);
+        let spelled = node.map_or(0.0, |node| self.spelled[node]);
+        /* Rounding must not leave a tiny weight on a prefix that only spells corpus words. */
+        match reach - spelled {
+            allowed if allowed > reach * 1e-9 => allowed,
+            _ => 0.0,
+        }
+    }
+
+    pub(crate) fn invent<R: Rng + CryptoRng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> Result<(String, Entropy), PassphraseError> {
+        /*
+            A function to invent one word that the blocklist allows, with the entropy of
+            its draws. Rejecting blocked words makes the entropy an upper bound.
+        */
+        for _ in 0..MAX_ATTEMPTS {
+            let (word, entropy) = self.draw(rng)?;
+            if !self
+                .blocklist
+                .as_ref()
+                .is_some_and(|blocklist| blocklist.blocks(&word))
+            {
+                return Ok((word, entropy));
+            }
+        }
+        Err(PassphraseError::NoReachableStates(format!(
+            "could not invent a word the blocklist allows in {MAX_ATTEMPTS} attempts"
+        )))
+    }
+
+    fn draw<R: Rng + CryptoRng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> Result<(String, Entropy), PassphraseError> {
+        /*
+            A function to invent one word with the entropy of its draws. Fails if no
+            word of the allowed length can be reached, which rounding can cause when
+            the allowed words are extremely unlikely.
+        */
+        let mut context = vec![None; self.model.order];
+        let mut node = Some(0);
+        let mut word = String::new();
+        let mut entropy = Entropy::default();
+        loop {
+            let length = word.chars().count();
+            let outcomes = self.probabilities(&context);
+            let weights = outcomes
+                .iter()
+                .map(|&(next, p)| {
+                    p * match next {
+                        None if node.is_some_and(|node| self.model.trie[node].word) => 0.0,
+                        None if (self.min_length..=self.max_length).contains(&length) => 1.0,
+                        None => 0.0,
+                        Some(next) => self.allowed(
+                            &shifted(&context, next),
+                            length + 1,
+                            node.and_then(|node| {
+                                self.model.trie[node].children.get(&next).copied()
+                            }),
+                        ),
+                    }
+                })
+                .collect::<Vec<f64>>();
+            let (index, step) = conditioned(rng, &weights).ok_or_else(|| {
+                PassphraseError::NoReachableStates(format!(
+                    "no invented word of {} to {} characters can be reached",
+                    self.min_length, self.max_length
+                ))
+            })?;
+            entropy += step;
+            match outcomes[index].0 {
+                None => return Ok((word, entropy)),
+                Some(next) => {
+                    word.push(next);
+                    node = node.and_then(|node| self.model.trie[node].children.get(&next).copied());
+                    context = shifted(&context, next);
+                }
+            }
+        }
+    }
+}
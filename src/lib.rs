@@ -0,0 +1,54 @@
+/*
+    A library for generating passphrases with Markov chains.
+
+    A Corpus of training text is turned into a TransitionModel, which a
+    PassphraseGenerator walks with a caller-supplied CSPRNG:
+
+        let corpus = Corpus::from_file("traindata/kanye_verses.txt")?;
+        let mut generator = PassphraseGenerator::builder(corpus).length(6).build()?;
+        let passphrase = generator.generate()?;
+        println!("{passphrase} ({:.2} bits)", passphrase.entropy.shannon_bits);
+*/
+
+mod blocklist;
+mod corpus;
+mod distribution;
+mod entropy;
+mod error;
+mod filter;
+mod generator;
+mod hash;
+mod include;
+mod invent;
+mod merge;
+mod model;
+mod persist;
+mod policy;
+mod rng;
+mod sampler;
+mod start;
+mod tokenizer;
+mod verbatim;
+mod vocabulary;
+
+pub use blocklist::{Blocklist, Pruning};
+pub use corpus::{Corpus, Source};
+pub use distribution::Distribution;
+pub use entropy::{shannon_entropy, Entropy, SamplingMode};
+pub use error::PassphraseError;
+pub use filter::TokenFilter;
+pub use generator::{
+    Capitalization, DeadEndPolicy, Format, Passphrase, PassphraseGenerator,
+    PassphraseGeneratorBuilder, Target, MAX_ATTEMPTS, MAX_CHAIN_WORDS,
+};
+pub use invent::{
+    CharacterModel, InventedWords, DEFAULT_CHAR_ORDER, MAX_CHAR_ORDER, MAX_INVENTED_LENGTH,
+};
+pub use model::{min_corpus_words, Context, TransitionModel, DEFAULT_ORDER, MAX_ORDER};
+pub use persist::FORMAT_VERSION;
+pub use policy::{Casing, CharClass, Policy, BUILTIN_POLICIES};
+pub use rng::EntropySource;
+pub use start::StartStrategy;
+pub use tokenizer::{CaseFolding, Normalization, Segmentation, Tokenizer, BOUNDARY};
+pub use verbatim::VerbatimIndex;
+pub use vocabulary::{Vocabulary, WordId};
@@ -2,116 +2,920 @@
     A simple passphrase generator using Markov chains.
 */
 
-use rand::Rng;
-use std::collections::HashMap;
+use markov_phrasegens::{
+    Blocklist, Capitalization, Corpus, DeadEndPolicy, EntropySource, InventedWords, Passphrase,
+    PassphraseError, PassphraseGenerator, Policy, Pruning, SamplingMode, StartStrategy, Target,
+    TokenFilter, Tokenizer, TransitionModel, DEFAULT_ORDER,
+};
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
 
-fn clean_word(word: &str) -> String {
+/* Paths to the default corpus, separated like PATH; used when no --corpus is given. */
+const CORPUS_ENV_VAR: &str = "MARKOV_PHRASEGENS_CORPUS";
+const DEFAULT_CORPUS: &str = "traindata/kanye_verses.txt";
+
+enum CorpusSource {
+    File(PathBuf),
+    Dir(PathBuf),
+    Stdin,
+}
+
+const USAGE: &str = "usage:
+  markov-phrasegens [generate] <length> | --min-bits <bits> | --min-surprisal <bits> [--model <file>] [--corpus <path|->]... [--corpus-dir <dir>]... [--weight <w>]... [--order <n>] [--seed <u64>] [--dead-end restart|fail] [--sampling weighted|uniform] [--backoff <min-successors>] [--max-verbatim <words>] [--end-at-boundary] [--start-strategy weighted|uniform|line-starts] [--start <word>] [--include <word>] [--invent | --mix-invented <n>] [--invented-length <min>-<max>] [--char-order <n>] [--tokenizer <settings>] [--min-word-length <n>] [--max-word-length <n>] [--min-frequency <n>] [--stopwords <file>]... [--separator <text>] [--case lower|upper|title] [--policy ad-default|banking|<file>] [--count <n>] [--format plain|jsonl|csv] [--max-chars <n>] [--blocklist builtin|<file>]... [--blocklist-leet]
+  markov-phrasegens train --output <file> [--model <file>] [--corpus <path|->]... [--corpus-dir <dir>]... [--weight <w>]... [--order <n>] [--tokenizer <settings>] [--min-word-length <n>] [--max-word-length <n>] [--min-frequency <n>] [--stopwords <file>]... [--blocklist builtin|<file>]... [--blocklist-leet]
+  markov-phrasegens merge --output <file> [--blocklist builtin|<file>]... [--blocklist-leet] <model> <model>...";
+
+#[derive(Clone, Copy, PartialEq)]
+enum OutputFormat {
+    /* One passphrase per line. */
+    Plain,
+    /* One JSON object per line, with the entropy and settings of each passphrase. */
+    JsonLines,
+    /* A header row, then one row per passphrase with the same fields as JSON Lines. */
+    Csv,
+}
+
+#[derive(PartialEq)]
+enum Command {
+    Train,
+    Generate,
+    Merge,
+}
+
+struct Options {
+    command: Command,
+    /* Every source with the weight given after it, if any. */
+    corpus: Vec<(CorpusSource, Option<f64>)>,
+    tokenizer: Option<Tokenizer>,
+    filter: TokenFilter,
+    stopwords: Vec<PathBuf>,
+    model: Option<PathBuf>,
+    output: Option<PathBuf>,
+    /* The models to merge. */
+    inputs: Vec<PathBuf>,
+    order: Option<usize>,
+    target: Option<Target>,
+    seed: Option<u64>,
+    dead_end: DeadEndPolicy,
+    sampling: SamplingMode,
+    backoff: Option<usize>,
+    max_verbatim: Option<usize>,
+    end_at_boundary: bool,
+    start: Option<StartStrategy>,
+    include: Option<String>,
+    invented: Option<InventedWords>,
+    policy: Option<Policy>,
+    /* The number of distinct passphrases to print, and how to print them. */
+    count: Option<usize>,
+    format: Option<OutputFormat>,
+    max_chars: Option<usize>,
+    /* The words to prune from the model before it is written or used. */
+    blocklist: Option<Blocklist>,
+    separator: String,
+    capitalization: Capitalization,
+}
+
+fn next_value<'a, I: Iterator<Item = &'a String>>(
+    args: &mut I,
+    flag: &str,
+) -> Result<&'a String, PassphraseError> {
+    args.next()
+        .ok_or_else(|| PassphraseError::Usage(format!("{flag} needs a value")))
+}
+
+fn parse_count(value: &str, flag: &str) -> Result<usize, PassphraseError> {
+    value.parse::<usize>().map_err(|_| {
+        PassphraseError::Usage(format!(
+            "{flag} must be a non-negative integer, got \"{value}\""
+        ))
+    })
+}
+
+fn parse_args(args: &[String]) -> Result<Options, PassphraseError> {
     /*
-    A function to normalize words by
-        1. Removing non-alphabetic characters
-        2. Converting to lowercase
+        A function to parse the command line. The first argument may name a command:
+        train writes a model file with --output, generate (the default) prints a passphrase,
+        and merge adds up the models it is given into one written with --output.
+         1. <length> generates a fixed number of words.
+         2. --min-bits <bits> extends the chain until the Shannon entropy target is reached,
+            --min-surprisal <bits> until the surprisal of its draws reaches it.
+         3. --seed <u64> replaces the OS CSPRNG with a reproducible ChaCha20 stream.
+         4. --dead-end <restart|fail> chooses what happens when the chain runs out of successors.
+         5. --separator <text> and --case <lower|upper|title> control formatting.
+         6. --corpus <path> (repeatable, - for stdin) and --corpus-dir <dir> pick the training text.
+         7. --model <file> generates from a model written by train instead of a corpus,
+            or with train, is updated with the text of --corpus and --corpus-dir.
+         8. --order <n> sets how many previous words the next word depends on (default 2).
+         9. --sampling <weighted|uniform> picks successors by corpus frequency or uniformly.
+        10. --max-verbatim <words> rejects passphrases that copy a longer run of the corpus.
+        11. --backoff <n> falls back to a lower order when a context has fewer than n successors.
+        12. --tokenizer <settings> sets how the corpus is split into words, e.g. unicode-words,nfc.
+        13. --min-word-length, --max-word-length, --min-frequency and --stopwords <file>
+            drop corpus words; a dropped word breaks the chain.
+        14. --end-at-boundary keeps generating past the target until a line or sentence ends.
+        15. --start-strategy <weighted|uniform|line-starts> or --start <word> picks how
+            the chain starts.
+        16. --include <word> only generates passphrases that contain the word.
+        17. --weight <w> after a --corpus or --corpus-dir sets that source's share of
+            the training data.
+        18. --invent makes the passphrase of invented words, --mix-invented <n> adds n of
+            them to the chain; --invented-length <min>-<max> and --char-order <n> set
+            how long they are and how many characters the next one depends on.
+        19. --policy <name|file> formats the passphrase to meet a built-in or custom
+            password policy instead of --separator and --case.
+        20. --count <n> prints n distinct passphrases from one model, and
+            --format <plain|jsonl|csv> prints them one per line, with their entropy
+            for JSON Lines and CSV.
+        21. --max-chars <n> limits the passphrase to n characters, with a length or
+            an entropy target.
+        22. --blocklist <builtin|file> (repeatable) prunes the words of the built-in or a
+            custom list from the model, and --blocklist-leet also matches leet spellings.
     */
-    word.chars()
-        .filter(|c| c.is_ascii_alphabetic())
-        .collect::<String>()
-        .to_lowercase()
+    let (command, rest) = match args.get(1).map(String::as_str) {
+        Some("train") => (Command::Train, &args[2..]),
+        Some("generate") => (Command::Generate, &args[2..]),
+        Some("merge") => (Command::Merge, &args[2..]),
+        _ => (Command::Generate, args.get(1..).unwrap_or_default()),
+    };
+    let mut args = rest.iter();
+    let mut corpus = Vec::new();
+    let mut tokenizer = None;
+    let mut filter = TokenFilter::default();
+    let mut stopwords = Vec::new();
+    let mut model = None;
+    let mut output = None;
+    let mut inputs = Vec::new();
+    let mut order = None;
+    let mut target = None;
+    let mut seed = None;
+    let mut dead_end = DeadEndPolicy::Restart;
+    let mut sampling = SamplingMode::Weighted;
+    let mut backoff = None;
+    let mut max_verbatim = None;
+    let mut end_at_boundary = false;
+    let mut start = None;
+    let mut include = None;
+    let mut invent = false;
+    let mut mix_invented = None;
+    let mut invented_length = None;
+    let mut char_order = None;
+    let mut policy = None;
+    let mut formatted = false;
+    let mut count = None;
+    let mut format = None;
+    let mut max_chars = None;
+    let mut blocklists = Vec::new();
+    let mut leet = false;
+    let mut separator = " ".to_string();
+    let mut capitalization = Capitalization::Lower;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--min-bits" | "--min-surprisal" => {
+                let bits = next_value(&mut args, arg)?;
+                let bits = bits.parse::<f64>().map_err(|_| {
+                    PassphraseError::InvalidLength(format!(
+                        "the entropy target must be a number, got \"{bits}\""
+                    ))
+                })?;
+                target = Some(match arg.as_str() {
+                    "--min-bits" => Target::MinBits(bits),
+                    _ => Target::MinSurprisal(bits),
+                })
+            }
+            "--seed" => {
+                let value = next_value(&mut args, arg)?;
+                seed = Some(value.parse::<u64>().map_err(|_| {
+                    PassphraseError::Usage(format!(
+                        "the seed must be a non-negative integer, got \"{value}\""
+                    ))
+                })?)
+            }
+            "--dead-end" => {
+                dead_end = match args.next().map(String::as_str) {
+                    Some("restart") => DeadEndPolicy::Restart,
+                    Some("fail") => DeadEndPolicy::Fail,
+                    _ => {
+                        return Err(PassphraseError::Usage(
+                            "--dead-end must be restart or fail".to_string(),
+                        ))
+                    }
+                }
+            }
+            "--sampling" => {
+                sampling = match next_value(&mut args, arg)?.as_str() {
+                    "weighted" => SamplingMode::Weighted,
+                    "uniform" => SamplingMode::Uniform,
+                    _ => {
+                        return Err(PassphraseError::Usage(
+                            "--sampling must be weighted or uniform".to_string(),
+                        ))
+                    }
+                }
+            }
+            "--backoff" => {
+                let value = next_value(&mut args, arg)?;
+                backoff = Some(value.parse::<usize>().map_err(|_| {
+                    PassphraseError::Usage(format!(
+                        "the backoff threshold must be a non-negative integer, got \"{value}\""
+                    ))
+                })?)
+            }
+            "--max-verbatim" => {
+                let value = next_value(&mut args, arg)?;
+                max_verbatim = Some(value.parse::<usize>().map_err(|_| {
+                    PassphraseError::Usage(format!(
+                        "the verbatim limit must be a non-negative integer, got \"{value}\""
+                    ))
+                })?)
+            }
+            "--end-at-boundary" => end_at_boundary = true,
+            "--start-strategy" | "--start" if start.is_some() => {
+                return Err(PassphraseError::Usage(
+                    "only one of --start-strategy and --start can be given".to_string(),
+                ))
+            }
+            "--start-strategy" => {
+                start = Some(match next_value(&mut args, arg)?.as_str() {
+                    "weighted" => StartStrategy::Weighted,
+                    "uniform" => StartStrategy::Uniform,
+                    "line-starts" => StartStrategy::LineStarts,
+                    _ => {
+                        return Err(PassphraseError::Usage(
+                            "--start-strategy must be weighted, uniform or line-starts".to_string(),
+                        ))
+                    }
+                })
+            }
+            "--start" => start = Some(StartStrategy::Word(next_value(&mut args, arg)?.clone())),
+            "--include" => include = Some(next_value(&mut args, arg)?.clone()),
+            "--invent" => invent = true,
+            "--mix-invented" => mix_invented = Some(parse_count(next_value(&mut args, arg)?, arg)?),
+            "--invented-length" => {
+                let value = next_value(&mut args, arg)?;
+                let (min, max) = value.split_once('-').unwrap_or((value, value));
+                invented_length = Some((parse_count(min, arg)?, parse_count(max, arg)?));
+            }
+            "--char-order" => char_order = Some(parse_count(next_value(&mut args, arg)?, arg)?),
+            "--corpus" => corpus.push((
+                match next_value(&mut args, arg)?.as_str() {
+                    "-" => CorpusSource::Stdin,
+                    path => CorpusSource::File(PathBuf::from(path)),
+                },
+                None,
+            )),
+            "--corpus-dir" => corpus.push((
+                CorpusSource::Dir(PathBuf::from(next_value(&mut args, arg)?)),
+                None,
+            )),
+            "--weight" => {
+                let value = next_value(&mut args, arg)?;
+                let weight = value.parse::<f64>().map_err(|_| {
+                    PassphraseError::Usage(format!("--weight must be a number, got \"{value}\""))
+                })?;
+                match corpus.last_mut() {
+                    Some((_, slot @ None)) => *slot = Some(weight),
+                    Some(_) => {
+                        return Err(PassphraseError::Usage(
+                            "--weight is given twice for the same source".to_string(),
+                        ))
+                    }
+                    None => {
+                        return Err(PassphraseError::Usage(
+                            "--weight must follow a --corpus or --corpus-dir".to_string(),
+                        ))
+                    }
+                }
+            }
+            "--tokenizer" => tokenizer = Some(next_value(&mut args, arg)?.parse::<Tokenizer>()?),
+            "--min-word-length" => {
+                filter.min_length = parse_count(next_value(&mut args, arg)?, arg)?
+            }
+            "--max-word-length" => {
+                filter.max_length = Some(parse_count(next_value(&mut args, arg)?, arg)?)
+            }
+            "--min-frequency" => {
+                let value = next_value(&mut args, arg)?;
+                filter.min_frequency = u32::try_from(parse_count(value, arg)?).map_err(|_| {
+                    PassphraseError::Usage(format!(
+                        "{arg} must be at most {}, got \"{value}\"",
+                        u32::MAX
+                    ))
+                })?
+            }
+            "--stopwords" => stopwords.push(PathBuf::from(next_value(&mut args, arg)?)),
+            "--model" => model = Some(PathBuf::from(next_value(&mut args, arg)?)),
+            "--output" => output = Some(PathBuf::from(next_value(&mut args, arg)?)),
+            "--order" => {
+                let value = next_value(&mut args, arg)?;
+                order = Some(value.parse::<usize>().map_err(|_| {
+                    PassphraseError::Usage(format!(
+                        "the order must be a non-negative integer, got \"{value}\""
+                    ))
+                })?)
+            }
+            "--policy" => {
+                let name = next_value(&mut args, arg)?;
+                policy = Some(match Policy::builtin(name) {
+                    Some(policy) => policy,
+                    None => Policy::load(name)?,
+                })
+            }
+            "--count" => match parse_count(next_value(&mut args, arg)?, arg)? {
+                0 => {
+                    return Err(PassphraseError::Usage(
+                        "--count must be at least 1".to_string(),
+                    ))
+                }
+                n => count = Some(n),
+            },
+            "--blocklist" => blocklists.push(next_value(&mut args, arg)?.clone()),
+            "--blocklist-leet" => leet = true,
+            "--max-chars" => max_chars = Some(parse_count(next_value(&mut args, arg)?, arg)?),
+            "--format" => {
+                format = Some(match next_value(&mut args, arg)?.as_str() {
+                    "plain" => OutputFormat::Plain,
+                    "jsonl" => OutputFormat::JsonLines,
+                    "csv" => OutputFormat::Csv,
+                    _ => {
+                        return Err(PassphraseError::Usage(
+                            "--format must be plain, jsonl or csv".to_string(),
+                        ))
+                    }
+                })
+            }
+            "--separator" => {
+                separator = next_value(&mut args, arg)?.clone();
+                formatted = true;
+            }
+            "--case" => {
+                formatted = true;
+                capitalization = match args.next().map(String::as_str) {
+                    Some("lower") => Capitalization::Lower,
+                    Some("upper") => Capitalization::Upper,
+                    Some("title") => Capitalization::Title,
+                    _ => {
+                        return Err(PassphraseError::Usage(
+                            "--case must be lower, upper or title".to_string(),
+                        ))
+                    }
+                }
+            }
+            flag if flag.starts_with("--") => {
+                return Err(PassphraseError::Usage(format!("unknown option {flag}")))
+            }
+            path if command == Command::Merge => inputs.push(PathBuf::from(path)),
+            length => {
+                target = Some(Target::Words(length.parse::<usize>().map_err(|_| {
+                    PassphraseError::InvalidLength(format!(
+                        "the passphrase length must be a positive integer, got \"{length}\""
+                    ))
+                })?))
+            }
+        }
+    }
+    let usage = || PassphraseError::Usage(USAGE.to_string());
+    match command {
+        Command::Train if output.is_none() || target.is_some() => return Err(usage()),
+        Command::Generate if target.is_none() || output.is_some() => return Err(usage()),
+        Command::Merge if output.is_none() || inputs.len() < 2 => return Err(usage()),
+        Command::Merge if model.is_some() || !corpus.is_empty() => {
+            return Err(PassphraseError::Usage(
+                "merge takes the models to merge as arguments, not --model or --corpus".to_string(),
+            ))
+        }
+        Command::Train if model.is_some() && corpus.is_empty() => {
+            return Err(PassphraseError::Usage(
+                "train --model needs the new text to add with --corpus or --corpus-dir".to_string(),
+            ))
+        }
+        _ => {}
+    }
+    if command == Command::Generate && model.is_some() && !corpus.is_empty() {
+        return Err(PassphraseError::Usage(
+            "--model and --corpus cannot be used together".to_string(),
+        ));
+    }
+    let filtered = filter != TokenFilter::default() || !stopwords.is_empty();
+    if command == Command::Generate && model.is_some() && filtered {
+        return Err(PassphraseError::Usage(
+            "word filters cannot be used with --model, which was filtered when it was trained"
+                .to_string(),
+        ));
+    }
+    if model.is_some() && tokenizer.is_some() {
+        return Err(PassphraseError::Usage(
+            "--tokenizer cannot be used with --model, which keeps the tokenizer it was trained with"
+                .to_string(),
+        ));
+    }
+    if policy.is_some() && formatted {
+        return Err(PassphraseError::Usage(
+            "--policy sets the separator and case itself, so --separator and --case cannot be used with it"
+                .to_string(),
+        ));
+    }
+    if invent && mix_invented.is_some() {
+        return Err(PassphraseError::Usage(
+            "--invent and --mix-invented cannot be used together".to_string(),
+        ));
+    }
+    let blocklist = match blocklists.is_empty() {
+        true if leet => {
+            return Err(PassphraseError::Usage(
+                "--blocklist-leet needs a --blocklist".to_string(),
+            ))
+        }
+        true => None,
+        false => {
+            let mut blocklist = Blocklist::new(leet);
+            for name in &blocklists {
+                match name.as_str() {
+                    "builtin" => blocklist.add_builtin(),
+                    path => blocklist.add_file(path)?,
+                }
+            }
+            Some(blocklist)
+        }
+    };
+    let invented = match (
+        invent || mix_invented.is_some(),
+        invented_length,
+        char_order,
+    ) {
+        (true, _, _) => {
+            let defaults = InventedWords::default();
+            let (min_length, max_length) =
+                invented_length.unwrap_or((defaults.min_length, defaults.max_length));
+            Some(InventedWords {
+                order: char_order.unwrap_or(defaults.order),
+                min_length,
+                max_length,
+                mix: mix_invented,
+            })
+        }
+        (false, None, None) => None,
+        (false, _, _) => {
+            return Err(PassphraseError::Usage(
+                "--invented-length and --char-order need --invent or --mix-invented".to_string(),
+            ))
+        }
+    };
+    Ok(Options {
+        command,
+        corpus,
+        tokenizer,
+        filter,
+        stopwords,
+        model,
+        output,
+        inputs,
+        order,
+        target,
+        seed,
+        dead_end,
+        sampling,
+        backoff,
+        max_verbatim,
+        end_at_boundary,
+        start,
+        include,
+        invented,
+        policy,
+        count,
+        format,
+        max_chars,
+        blocklist,
+        separator,
+        capitalization,
+    })
 }
 
-fn create_transition_matrix(words: Vec<String>) -> HashMap<(String, String), Vec<String>> {
+fn load_corpus(options: &Options, tokenizer: Tokenizer) -> Result<Corpus, PassphraseError> {
     /*
-        A function to create a transition matrix from a list of words.
-        Heuristic states that the next word is dependent on the previous two words.
+        A function to load the training text at runtime. Without any --corpus or
+        --corpus-dir, the paths in MARKOV_PHRASEGENS_CORPUS are used, falling back to
+        traindata/kanye_verses.txt relative to the current directory. Stopword files
+        are split with the same tokenizer as the corpus, so they match its words.
+        Every --corpus and --corpus-dir is its own source, weighted by the --weight
+        that follows it.
     */
-
-    let mut transition_matrix = HashMap::new();
-    for window in words.windows(3) {
-        if let [w0, w1, w2] = &window {
-            let entry = transition_matrix
-                .entry((w0.clone(), w1.clone()))
-                .or_insert_with(Vec::new);
-            entry.push(w2.clone());
+    let mut corpus = Corpus::with_tokenizer(tokenizer);
+    let mut filter = options.filter.clone();
+    for path in &options.stopwords {
+        let text = fs::read_to_string(path).map_err(|source| PassphraseError::MissingCorpus {
+            path: path.clone(),
+            source,
+        })?;
+        filter.stopwords.extend(corpus.tokenizer().tokenize(&text));
+    }
+    corpus.set_filter(filter);
+    if options.corpus.is_empty() {
+        match env::var_os(CORPUS_ENV_VAR) {
+            Some(paths) => {
+                for path in env::split_paths(&paths) {
+                    corpus.add_path(path)?;
+                }
+            }
+            None => corpus.add_file(DEFAULT_CORPUS)?,
         }
     }
-    transition_matrix
+    for (source, weight) in &options.corpus {
+        let name = match source {
+            CorpusSource::File(path) | CorpusSource::Dir(path) => path.display().to_string(),
+            CorpusSource::Stdin => "<stdin>".to_string(),
+        };
+        corpus.add_source(&name, *weight)?;
+        match source {
+            CorpusSource::File(path) => corpus.add_file(path)?,
+            CorpusSource::Dir(dir) => corpus.add_dir(dir)?,
+            CorpusSource::Stdin => corpus.add_reader(io::stdin().lock(), "<stdin>")?,
+        }
+    }
+    Ok(corpus)
 }
 
-fn markov_chain(
-    transition_matrix: &HashMap<(String, String), Vec<String>>,
-    length: usize,
-    w0: String,
-    w1: String,
-    w2: String,
-) -> Vec<String> {
+fn train(options: Options) -> Result<(), PassphraseError> {
+    let output = options.output.as_ref().expect("train requires --output");
+    if let Some(path) = &options.model {
+        return update(&options, path, output);
+    }
+    let corpus = load_corpus(&options, options.tokenizer.unwrap_or_default())?;
+    let mut model = TransitionModel::train(&corpus, options.order.unwrap_or(DEFAULT_ORDER))?;
+    let pruning = prune(&options, &mut model)?;
+    model.save(output)?;
+    println!(
+        "Trained an order {} model on {} words ({} contexts, tokenizer {}) and wrote it to {}",
+        model.order(),
+        model.word_count(),
+        model.context_count(),
+        model.tokenizer(),
+        output.display()
+    );
+    if let Some(pruning) = pruning {
+        println!("{}", describe_pruning(&pruning));
+    }
+    Ok(())
+}
+
+fn prune(
+    options: &Options,
+    model: &mut TransitionModel,
+) -> Result<Option<Pruning>, PassphraseError> {
     /*
-        A function to apply a Markov chain to generate the next word in a sequence.
+        A function to prune the words of --blocklist from a model before it is written.
     */
-    let mut rng = rand::thread_rng();
-    let mut result = vec![w2.clone()];
-    let (mut _w0, mut w1, mut w2) = (w0, w1, w2);
-    for _ in 0..length - 1 {
-        if let Some(next_words) = transition_matrix.get(&(w1.clone(), w2.clone())) {
-            let next_word = next_words[rng.gen_range(0..next_words.len())].clone();
-            result.push(next_word.clone());
-            _w0 = w1;
-            w1 = w2;
-            w2 = next_word;
-        }
+    options
+        .blocklist
+        .as_ref()
+        .map(|blocklist| model.prune(blocklist))
+        .transpose()
+}
+
+fn describe_pruning(pruning: &Pruning) -> String {
+    format!(
+        "The blocklist removed {} words ({} occurrences) from the model: a start state now carries {:.2} bits instead of {:.2}, and a step of the chain {:.2} bits instead of {:.2} on average (Shannon)",
+        pruning.words.len(),
+        pruning.occurrences,
+        pruning.start_after,
+        pruning.start_before,
+        pruning.step_after,
+        pruning.step_before
+    )
+}
+
+fn update(options: &Options, path: &Path, output: &Path) -> Result<(), PassphraseError> {
+    /*
+        A function to add the text of --corpus and --corpus-dir to a saved model,
+        split with the tokenizer the model was trained with.
+    */
+    let mut model = TransitionModel::load(path)?;
+    if options.order.is_some_and(|order| order != model.order()) {
+        return Err(PassphraseError::InvalidOrder(format!(
+            "{} is an order {} model, it cannot be updated to another order",
+            path.display(),
+            model.order()
+        )));
+    }
+    let before = model.word_count();
+    let corpus = load_corpus(options, *model.tokenizer())?;
+    model.update(&corpus)?;
+    let pruning = prune(options, &mut model)?;
+    model.save(output)?;
+    println!(
+        "Added {} words to the order {} model {} ({} words, {} contexts) and wrote it to {}",
+        model.word_count() - before,
+        model.order(),
+        path.display(),
+        model.word_count(),
+        model.context_count(),
+        output.display()
+    );
+    if let Some(pruning) = pruning {
+        println!("{}", describe_pruning(&pruning));
     }
-    result
+    Ok(())
 }
 
-fn read_training_data(filename: &str) -> Vec<String> {
+fn merge(options: Options) -> Result<(), PassphraseError> {
     /*
-        A function to read training data from a file.
-        Note:
-         1. The file should contain a list of words separated by spaces.
-         2. The file should be saved in the traindata directory.
+        A function to merge the saved models given as arguments, in order, into the
+        first of them.
     */
-    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
-        .join("traindata")
-        .join(filename);
-    fs::read_to_string(path)
-        .expect("Failed to read training data file")
-        .split_whitespace()
-        .map(clean_word)
-        .collect()
+    let mut inputs = options.inputs.iter();
+    let mut model = TransitionModel::load(inputs.next().expect("merge requires models"))?;
+    for path in inputs {
+        model.merge(&TransitionModel::load(path)?)?;
+    }
+    let pruning = prune(&options, &mut model)?;
+    let output = options.output.as_ref().expect("merge requires --output");
+    model.save(output)?;
+    println!(
+        "Merged {} models into an order {} model on {} words ({} contexts) and wrote it to {}",
+        options.inputs.len(),
+        model.order(),
+        model.word_count(),
+        model.context_count(),
+        output.display()
+    );
+    if let Some(pruning) = pruning {
+        println!("{}", describe_pruning(&pruning));
+    }
+    Ok(())
+}
+
+fn generate(options: Options) -> Result<(), PassphraseError> {
+    let builder = match &options.model {
+        Some(path) => PassphraseGenerator::from_model(TransitionModel::load(path)?),
+        None => PassphraseGenerator::builder(load_corpus(
+            &options,
+            options.tokenizer.unwrap_or_default(),
+        )?),
+    };
+    let builder = match options.order {
+        Some(order) => builder.order(order),
+        None => builder,
+    };
+    let builder = match options.backoff {
+        Some(min_successors) => builder.backoff(min_successors),
+        None => builder,
+    };
+    let builder = match options.max_verbatim {
+        Some(words) => builder.max_verbatim(words),
+        None => builder,
+    };
+    let builder = match options.start.clone() {
+        Some(start) => builder.start(start),
+        None => builder,
+    };
+    let builder = match &options.include {
+        Some(word) => builder.include(word),
+        None => builder,
+    };
+    let builder = match options.invented {
+        Some(invented) => builder.invented(invented),
+        None => builder,
+    };
+    let builder = match options.max_chars {
+        Some(chars) => builder.max_chars(chars),
+        None => builder,
+    };
+    let builder = match options.blocklist.clone() {
+        Some(blocklist) => builder.blocklist(blocklist),
+        None => builder,
+    };
+    let builder = match options.policy.clone() {
+        Some(policy) => builder.policy(policy),
+        None => builder,
+    };
+    let mut generator = builder
+        .rng(EntropySource::from_seed(options.seed))
+        .target(options.target.expect("generate requires a target"))
+        .dead_end(options.dead_end)
+        .sampling(options.sampling)
+        .end_at_boundary(options.end_at_boundary)
+        .separator(&options.separator)
+        .capitalization(options.capitalization)
+        .build()?;
+    if options.count.is_none() && options.format.is_none() {
+        let passphrase = generator.generate()?;
+        report(&options, &generator, &passphrase);
+        return Ok(());
+    }
+    let batch = generator.generate_batch(options.count.unwrap_or(1))?;
+    let format = options.format.unwrap_or(OutputFormat::Plain);
+    if format == OutputFormat::Csv {
+        println!("{}", FIELDS.join(","));
+    }
+    for (index, passphrase) in batch.iter().enumerate() {
+        let values = fields(index + 1, &generator, passphrase);
+        match format {
+            OutputFormat::Plain => println!("{passphrase}"),
+            OutputFormat::JsonLines => println!(
+                "{{{}}}",
+                FIELDS
+                    .iter()
+                    .zip(&values)
+                    .map(|(name, value)| format!("\"{name}\":{}", value.json()))
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+            OutputFormat::Csv => println!(
+                "{}",
+                values
+                    .iter()
+                    .map(Field::csv)
+                    .collect::<Vec<String>>()
+                    .join(",")
+            ),
+        }
+    }
+    if let Some(pruning) = generator.pruning() {
+        eprintln!("{}", describe_pruning(pruning));
+    }
+    if generator.rng().is_insecure() {
+        eprintln!(
+            "INSECURE: these passphrases were generated from a fixed seed and can be reproduced by anyone who knows it. Use them for tests and demos only."
+        );
+    }
+    Ok(())
 }
 
-fn generate_passphrase(words: Vec<String>, length: usize) -> String {
+fn report(
+    options: &Options,
+    generator: &PassphraseGenerator<EntropySource>,
+    passphrase: &Passphrase,
+) {
     /*
-        A function to generate a passphrase using a Markov chain.
+        A function to print a single passphrase with an account of its entropy.
     */
-    let clean_words = words
-        .into_iter()
-        .map(|w| clean_word(&w))
-        .collect::<Vec<String>>();
-    let transition_matrix = create_transition_matrix(clean_words.clone());
-    let start_index = rand::thread_rng().gen_range(0..clean_words.len() - 3);
-    let chain = markov_chain(
-        &transition_matrix,
-        length,
-        clean_words[start_index].clone(),
-        clean_words[start_index + 1].clone(),
-        clean_words[start_index + 2].clone(),
+    println!(
+        "\nYour randomly generated {} length passphrase is:\n\n{}\n\nEntropy: {:.2} bits (Shannon), {:.2} bits (surprisal)",
+        passphrase.words.len(),
+        passphrase,
+        passphrase.entropy.shannon_bits,
+        passphrase.entropy.surprisal_bits
     );
-    chain.join(" ")
+    if options
+        .invented
+        .is_none_or(|invented| invented.mix.is_some())
+    {
+        println!(
+            "of which the start state ({}) contributes {:.2} bits (Shannon), {:.2} bits (surprisal)",
+            generator.start_strategy(),
+            passphrase.start_entropy.shannon_bits,
+            passphrase.start_entropy.surprisal_bits
+        );
+    }
+    if options
+        .invented
+        .is_some_and(|invented| invented.mix.is_some())
+    {
+        println!(
+            "and the invented words and their positions contribute {:.2} bits (Shannon), {:.2} bits (surprisal)",
+            passphrase.invented_entropy.shannon_bits,
+            passphrase.invented_entropy.surprisal_bits
+        );
+    }
+    if let Some(chars) = options.max_chars {
+        /* Steps can count negatively, so a sum that rounds to zero must not print as -0.00. */
+        let rounded = |bits: f64| match bits.abs() < 0.005 {
+            true => 0.0,
+            false => bits,
+        };
+        println!(
+            "and the {chars} character limit removed {:.2} bits (Shannon), {:.2} bits (surprisal) from its draws",
+            rounded(passphrase.length_reduction.shannon_bits),
+            rounded(passphrase.length_reduction.surprisal_bits)
+        );
+    }
+    if let Some(policy) = generator.policy() {
+        println!(
+            "and the {} policy contributes {:.2} bits (Shannon), {:.2} bits (surprisal)",
+            policy.name,
+            passphrase.policy_entropy.shannon_bits,
+            passphrase.policy_entropy.surprisal_bits
+        );
+    }
+    if let Some(bits) = generator.min_entropy() {
+        println!(
+            "\nThe most likely passphrase of this generator carries {bits:.2} bits (min-entropy)"
+        );
+    }
+    if let Some(pruning) = generator.pruning() {
+        println!("\n{}", describe_pruning(pruning));
+    }
+    if generator.rng().is_insecure() {
+        println!(
+            "\nINSECURE: this passphrase was generated from a fixed seed and can be reproduced by anyone who knows it. Use it for tests and demos only."
+        );
+    }
+}
+
+/* The fields of every passphrase in JSON Lines and CSV output, in order. */
+const FIELDS: [&str; 14] = [
+    "index",
+    "passphrase",
+    "words",
+    "characters",
+    "shannon_bits",
+    "surprisal_bits",
+    "min_entropy_bits",
+    "start_shannon_bits",
+    "invented_shannon_bits",
+    "policy_shannon_bits",
+    "length_reduction_bits",
+    "start_strategy",
+    "policy",
+    "insecure",
+];
+
+enum Field {
+    Text(Option<String>),
+    Count(usize),
+    Bits(f64),
+    Flag(bool),
+}
+
+impl Field {
+    fn json(&self) -> String {
+        match self {
+            Field::Text(Some(text)) => {
+                let mut quoted = String::from("\"");
+                for c in text.chars() {
+                    match c {
+                        '"' => quoted.push_str("\\\""),
+                        '\\' => quoted.push_str("\\\\"),
+                        c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+                        c => quoted.push(c),
+                    }
+                }
+                quoted.push('"');
+                quoted
+            }
+            Field::Text(None) => "null".to_string(),
+            other => other.csv(),
+        }
+    }
+
+    fn csv(&self) -> String {
+        /*
+            Text is quoted when it holds a comma, a quote or a line break, with quotes
+            doubled, as RFC 4180 describes. A missing value is an empty field.
+        */
+        match self {
+            Field::Text(Some(text)) if text.contains([',', '"', '\n', '\r']) => {
+                format!("\"{}\"", text.replace('"', "\"\""))
+            }
+            Field::Text(text) => text.clone().unwrap_or_default(),
+            Field::Count(count) => count.to_string(),
+            Field::Bits(bits) => format!("{bits:.4}"),
+            Field::Flag(flag) => flag.to_string(),
+        }
+    }
+}
+
+fn fields(
+    index: usize,
+    generator: &PassphraseGenerator<EntropySource>,
+    passphrase: &Passphrase,
+) -> [Field; 14] {
+    [
+        Field::Count(index),
+        Field::Text(Some(passphrase.phrase.clone())),
+        Field::Count(passphrase.words.len()),
+        Field::Count(passphrase.phrase.chars().count()),
+        Field::Bits(passphrase.entropy.shannon_bits),
+        Field::Bits(passphrase.entropy.surprisal_bits),
+        match generator.min_entropy() {
+            Some(bits) => Field::Bits(bits),
+            None => Field::Text(None),
+        },
+        Field::Bits(passphrase.start_entropy.shannon_bits),
+        Field::Bits(passphrase.invented_entropy.shannon_bits),
+        Field::Bits(passphrase.policy_entropy.shannon_bits),
+        Field::Bits(passphrase.length_reduction.shannon_bits),
+        Field::Text(Some(generator.start_strategy().to_string())),
+        Field::Text(generator.policy().map(|policy| policy.name.clone())),
+        Field::Flag(generator.rng().is_insecure()),
+    ]
+}
+
+fn run(args: &[String]) -> Result<(), PassphraseError> {
+    let options = parse_args(args)?;
+    match options.command {
+        Command::Train => train(options),
+        Command::Generate => generate(options),
+        Command::Merge => merge(options),
+    }
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let length = args
-        .get(1)
-        .expect("Please provide a passphrase length")
-        .parse::<usize>()
-        .expect("Length must be a positive integer");
-
-    let words = read_training_data("kanye_verses.txt");
-    let passphrase = generate_passphrase(words, length);
-    println!(
-        "\nYour randomly generated {} length passphrase is:\n\n{}",
-        length, passphrase
-    );
+    if let Err(error) = run(&args) {
+        eprintln!("error: {error}");
+        process::exit(error.exit_code());
+    }
 }
@@ -0,0 +1,358 @@
+/*
+    Updating a trained model with new text, and merging two trained models.
+
+    A model only holds counts, so adding text never needs the text it was trained on:
+    the counts of the new text are added to the counts already there. Both models
+    number their words independently, so the words of the second model are first
+    looked up, or added, in the vocabulary of the first. The counts of a model trained
+    with weights are scaled by whole numbers, so before two such models are added up,
+    their counts are scaled again until a unit of weight counts the same in both.
+*/
+
+use crate::corpus::{Corpus, WEIGHT_RESOLUTION};
+use crate::distribution::Distribution;
+use crate::error::PassphraseError;
+use crate::hash::Fnv1a;
+use crate::model::{line_starts, saturating_count, Context, TransitionModel};
+use crate::vocabulary::WordId;
+use std::collections::HashMap;
+use std::hash::Hash;
+
+fn summed<T: Ord + Hash>(counts: impl Iterator<Item = (T, u32)>) -> Distribution<T> {
+    /*
+        A function to build a distribution from counts that may repeat an outcome,
+        adding up the counts of each.
+    */
+    let mut sums: HashMap<T, u64> = HashMap::new();
+    for (item, count) in counts {
+        *sums.entry(item).or_insert(0) += count as u64;
+    }
+    Distribution::from_counts(
+        sums.into_iter()
+            .map(|(item, count)| (item, saturating_count(count))),
+    )
+}
+
+fn remapped(context: &Context, ids: &[WordId]) -> Context {
+    Context::new(
+        context
+            .words()
+            .iter()
+            .map(|word| ids[word.0 as usize])
+            .collect(),
+    )
+}
+
+fn scaled(count: u32, factor: u32) -> u32 {
+    saturating_count(count as u64 * factor as u64)
+}
+
+fn scale_table(table: &mut HashMap<Context, Distribution<WordId>>, factor: u32) {
+    for successors in table.values_mut() {
+        *successors = Distribution::from_counts(
+            successors
+                .iter()
+                .map(|(&word, count)| (word, scaled(count, factor))),
+        );
+    }
+}
+
+fn merge_table(
+    table: &mut HashMap<Context, Distribution<WordId>>,
+    other: &HashMap<Context, Distribution<WordId>>,
+    ids: &[WordId],
+    factor: u32,
+) {
+    for (context, successors) in other {
+        let context = remapped(context, ids);
+        let merged = summed(
+            table
+                .get(&context)
+                .into_iter()
+                .flat_map(|existing| existing.iter().map(|(&word, count)| (word, count)))
+                .chain(
+                    successors
+                        .iter()
+                        .map(|(word, count)| (ids[word.0 as usize], scaled(count, factor))),
+                ),
+        );
+        table.insert(context, merged);
+    }
+}
+
+fn common_scale(scale: f64, other: f64) -> (u32, u32) {
+    /*
+        A function to find the smallest whole numbers to multiply the counts of two
+        weighted models by, so that a source of weight 1 adds up to the same count in
+        both to within about 1%. A model without counts has a scale of 0 and needs none.
+    */
+    if scale <= 0.0 || other <= 0.0 {
+        return (1, 1);
+    }
+    if scale < other {
+        let (other, scale) = common_scale(other, scale);
+        return (scale, other);
+    }
+    let ratio = scale / other;
+    (1..)
+        .map(|factor| (factor, factor as f64 * ratio))
+        .find(|&(_, count)| (count.round() - count).abs() <= count / WEIGHT_RESOLUTION)
+        .map(|(factor, count)| (factor, count.round() as u32))
+        .expect("a factor of WEIGHT_RESOLUTION / 2 is always close enough")
+}
+
+impl TransitionModel {
+    pub fn merge(&mut self, other: &TransitionModel) -> Result<(), PassphraseError> {
+        /*
+            A function to add the counts of another model to this one, as if both had
+            been trained on their texts together. The models must have the same order
+            and tokenizer, and either both or neither must have been trained with
+            weights. A model saved before format version 3 has no backoff tables,
+            and one saved before version 2 has no training text, so merging it drops
+            them. The corpus hash becomes a hash of both hashes.
+        */
+        if other.order != self.order {
+            return Err(PassphraseError::InvalidOrder(format!(
+                "cannot merge an order {} model into an order {} model",
+                other.order, self.order
+            )));
+        }
+        if other.tokenizer != self.tokenizer {
+            return Err(PassphraseError::InvalidModel(format!(
+                "cannot merge a model with tokenizer {} into one with tokenizer {}",
+                other.tokenizer, self.tokenizer
+            )));
+        }
+        let (factor, other_factor) = match (self.weight_scale, other.weight_scale) {
+            (None, None) => (1, 1),
+            (Some(scale), Some(other_scale)) => {
+                let (factor, other_factor) = common_scale(scale, other_scale);
+                self.weight_scale = Some(scale * factor as f64);
+                (factor, other_factor)
+            }
+            _ => {
+                return Err(PassphraseError::InvalidModel(
+                    "cannot merge a model trained with weights and one trained without".to_string(),
+                ))
+            }
+        };
+        if factor != 1 {
+            scale_table(&mut self.transitions, factor);
+            for table in &mut self.backoff {
+                scale_table(table, factor);
+            }
+        }
+        let ids = other
+            .vocabulary
+            .words()
+            .iter()
+            .map(|word| self.vocabulary.intern(word))
+            .collect::<Vec<WordId>>();
+        merge_table(
+            &mut self.transitions,
+            &other.transitions,
+            &ids,
+            other_factor,
+        );
+        if self.backoff.len() == other.backoff.len() {
+            for (table, other) in self.backoff.iter_mut().zip(&other.backoff) {
+                merge_table(table, other, &ids, other_factor);
+            }
+        } else {
+            self.backoff.clear();
+        }
+        self.starts = summed(
+            self.starts
+                .iter()
+                .map(|(context, count)| (context.clone(), scaled(count, factor)))
+                .chain(other.starts.iter().map(|(context, count)| {
+                    (remapped(context, &ids), scaled(count, other_factor))
+                })),
+        );
+        self.line_starts = line_starts(&self.transitions, self.order, self.boundary());
+        if self.documents.is_empty() || other.documents.is_empty() {
+            self.documents.clear();
+        } else {
+            self.documents.extend(other.documents.iter().map(|words| {
+                words
+                    .iter()
+                    .map(|word| ids[word.0 as usize])
+                    .collect::<Vec<WordId>>()
+            }));
+        }
+        let mut hasher = Fnv1a::new();
+        hasher.write(&self.corpus_hash.to_le_bytes());
+        hasher.write(&other.corpus_hash.to_le_bytes());
+        self.corpus_hash = hasher.finish();
+        Ok(())
+    }
+
+    pub fn update(&mut self, corpus: &Corpus) -> Result<(), PassphraseError> {
+        /*
+            A function to add the counts of new training text to the model. The corpus
+            must use the tokenizer the model was trained with, and have weights if the
+            model was trained with them, which then set the share of the new text
+            relative to the weights the model was trained with. Its word filters only
+            see the new text, so a minimum frequency counts occurrences in it alone.
+        */
+        if *corpus.tokenizer() != self.tokenizer {
+            return Err(PassphraseError::Usage(format!(
+                "the new text must be split with the model's tokenizer {}, not {}",
+                self.tokenizer,
+                corpus.tokenizer()
+            )));
+        }
+        match (self.weight_scale, corpus.weight_scale()) {
+            (Some(_), None) => Err(PassphraseError::Usage(
+                "the model was trained with weights, so the new text needs a --weight too"
+                    .to_string(),
+            )),
+            (None, Some(_)) => Err(PassphraseError::Usage(
+                "the model was trained without weights, so the new text cannot have a --weight"
+                    .to_string(),
+            )),
+            _ => self.merge(&TransitionModel::count(corpus, self.order)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entropy::SamplingMode;
+
+    const FIRST: &str = "the quick brown fox jumps over the lazy dog and the dog sleeps";
+    const SECOND: &str = "a lazy cat sleeps under the warm sun while the brown fox runs";
+
+    fn corpus(texts: &[(&str, Option<f64>)]) -> Corpus {
+        let mut corpus = Corpus::new();
+        for (index, &(text, weight)) in texts.iter().enumerate() {
+            corpus.add_source(&format!("text {index}"), weight).unwrap();
+            corpus.add_text(text);
+        }
+        corpus
+    }
+
+    fn trained(texts: &[(&str, Option<f64>)], order: usize) -> TransitionModel {
+        TransitionModel::train(&corpus(texts), order).unwrap()
+    }
+
+    fn same_file(mut model: TransitionModel, whole: &TransitionModel) {
+        /* The files may only differ in the corpus hash, and so in the checksum. */
+        model.corpus_hash = whole.corpus_hash;
+        assert_eq!(model.to_bytes(), whole.to_bytes());
+    }
+
+    #[test]
+    fn merge_matches_training_on_the_whole_text() {
+        for order in 0..=2 {
+            let whole = trained(&[(FIRST, None), (SECOND, None)], order);
+            let mut model = trained(&[(FIRST, None)], order);
+            model.merge(&trained(&[(SECOND, None)], order)).unwrap();
+            assert_ne!(model.corpus_hash, whole.corpus_hash);
+            same_file(model, &whole);
+        }
+    }
+
+    #[test]
+    fn update_matches_training_on_the_whole_text() {
+        for order in 0..=2 {
+            let whole = trained(&[(FIRST, None), (SECOND, None)], order);
+            let mut model = trained(&[(FIRST, None)], order);
+            model.update(&corpus(&[(SECOND, None)])).unwrap();
+            same_file(model, &whole);
+        }
+    }
+
+    #[test]
+    fn merge_remaps_the_words_of_the_other_model() {
+        let mut model = trained(&[(SECOND, None)], 1);
+        let other = trained(&[(FIRST, None)], 1);
+        let fox = other.vocabulary.id("fox").unwrap();
+        assert_ne!(Some(fox), model.vocabulary.id("fox"));
+        model.merge(&other).unwrap();
+        let id = |word: &str| model.vocabulary.id(word).unwrap();
+        let successors = model.successors(&Context::new(vec![id("fox")])).unwrap();
+        let mut words = successors
+            .iter()
+            .map(|(&word, count)| (model.vocabulary.word(word), count))
+            .collect::<Vec<(&str, u32)>>();
+        words.sort();
+        assert_eq!(words, vec![("jumps", 1), ("runs", 1)]);
+        assert_eq!(model.vocabulary.len(), {
+            let mut words = FIRST
+                .split(' ')
+                .chain(SECOND.split(' '))
+                .collect::<Vec<_>>();
+            words.sort();
+            words.dedup();
+            words.len()
+        });
+    }
+
+    #[test]
+    fn common_scale_finds_the_smallest_whole_factors() {
+        assert_eq!(common_scale(2.0, 2.0), (1, 1));
+        assert_eq!(common_scale(3.0, 1.0), (1, 3));
+        assert_eq!(common_scale(1.0, 3.0), (3, 1));
+        assert_eq!(common_scale(3.0, 2.0), (2, 3));
+        assert_eq!(common_scale(0.0, 5.0), (1, 1));
+        for (scale, other) in [(7.0, 3.0), (1.0, 1.37), (10.0, 0.3)] {
+            let (factor, other_factor) = common_scale(scale, other);
+            let (count, other_count) = (scale * factor as f64, other * other_factor as f64);
+            assert!((count - other_count).abs() <= count.max(other_count) / WEIGHT_RESOLUTION);
+        }
+    }
+
+    #[test]
+    fn weighted_merge_keeps_the_share_of_each_text() {
+        /*
+            Training with weights scales the counts of both texts together, while a
+            merge rescales the counts of each model, so the counts differ but the
+            probabilities agree to within the weight resolution.
+        */
+        for (first, second) in [(1.0, 3.0), (2.0, 0.7)] {
+            let whole = trained(&[(FIRST, Some(first)), (SECOND, Some(second))], 1);
+            let mut model = trained(&[(FIRST, Some(first))], 1);
+            model.merge(&trained(&[(SECOND, Some(second))], 1)).unwrap();
+            let mut updated = trained(&[(FIRST, Some(first))], 1);
+            updated.update(&corpus(&[(SECOND, Some(second))])).unwrap();
+            for model in [model, updated] {
+                assert_eq!(model.vocabulary.words(), whole.vocabulary.words());
+                assert_eq!(model.transitions.len(), whole.transitions.len());
+                for (context, successors) in &whole.transitions {
+                    let merged = &model.transitions[context];
+                    assert_eq!(merged.len(), successors.len());
+                    for (word, probability) in successors.probabilities(SamplingMode::Weighted) {
+                        let share = merged.probability(word, SamplingMode::Weighted);
+                        assert!(
+                            (share - probability).abs() < 0.02,
+                            "{share} != {probability}"
+                        );
+                    }
+                }
+                for (context, probability) in whole.starts.probabilities(SamplingMode::Weighted) {
+                    let share = model.starts.probability(context, SamplingMode::Weighted);
+                    assert!(
+                        (share - probability).abs() < 0.02,
+                        "{share} != {probability}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn merge_rejects_weighted_and_unweighted_models() {
+        let mut model = trained(&[(FIRST, Some(1.0))], 1);
+        assert!(matches!(
+            model.merge(&trained(&[(SECOND, None)], 1)),
+            Err(PassphraseError::InvalidModel(_))
+        ));
+        let mut model = trained(&[(FIRST, None)], 1);
+        assert!(matches!(
+            model.update(&corpus(&[(SECOND, Some(1.0))])),
+            Err(PassphraseError::Usage(_))
+        ));
+    }
+}
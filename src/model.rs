@@ -0,0 +1,380 @@
+/*
+    The transition model learned from a corpus.
+*/
+
+use crate::corpus::Corpus;
+use crate::distribution::Distribution;
+use crate::entropy::SamplingMode;
+use crate::error::PassphraseError;
+use crate::tokenizer::{Tokenizer, BOUNDARY};
+use crate::vocabulary::{Vocabulary, WordId};
+use std::collections::HashMap;
+
+pub const DEFAULT_ORDER: usize = 2;
+pub const MAX_ORDER: usize = 8;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Context(Vec<WordId>);
+
+impl Context {
+    /*
+        The words the next word depends on, oldest first. Its length is the model order.
+    */
+    pub fn new(words: Vec<WordId>) -> Context {
+        Context(words)
+    }
+
+    pub fn words(&self) -> &[WordId] {
+        &self.0
+    }
+
+    pub fn last(&self) -> Option<WordId> {
+        self.0.last().copied()
+    }
+
+    pub fn suffix(&self, order: usize) -> Context {
+        /*
+            The last `order` words, the context a lower-order model would see.
+        */
+        Context(self.0[self.0.len() - order.min(self.0.len())..].to_vec())
+    }
+
+    pub(crate) fn shift(&mut self, next_word: WordId) {
+        /*
+            Slides the context one word forward. An empty (order 0) context stays empty.
+        */
+        if !self.0.is_empty() {
+            self.0.remove(0);
+            self.0.push(next_word);
+        }
+    }
+
+    pub(crate) fn mark_boundary(&mut self, boundary: Option<WordId>) {
+        /*
+            Replaces every word before the last boundary with the boundary marker, so
+            nothing from before the end of a line conditions the words of the next one.
+        */
+        if let Some(boundary) = boundary {
+            if let Some(last) = self.0.iter().rposition(|&word| word == boundary) {
+                self.0[..last].fill(boundary);
+            }
+        }
+    }
+}
+
+pub fn min_corpus_words(order: usize) -> usize {
+    /*
+        The smallest document that yields a start state with a following word.
+    */
+    order + 1
+}
+
+fn padded(words: &[WordId], order: usize, boundary: Option<WordId>) -> Vec<WordId> {
+    /*
+        A document that opens at a line start is padded to `order` boundary markers,
+        so that the first words of a line get a context of their own.
+    */
+    match (boundary, words.split_first()) {
+        (Some(boundary), Some((&first, rest))) if first == boundary => {
+            let mut padded = vec![boundary; order];
+            padded.extend_from_slice(rest);
+            padded
+        }
+        _ => words.to_vec(),
+    }
+}
+
+pub(crate) fn saturating_count(count: u64) -> u32 {
+    /* Weighted counts are summed in 64 bits and stored in 32, saturating if they overflow. */
+    count.min(u32::MAX as u64) as u32
+}
+
+fn create_transition_matrix(
+    documents: &[Vec<WordId>],
+    weights: &[u32],
+    order: usize,
+    boundary: Option<WordId>,
+) -> HashMap<Context, Distribution<WordId>> {
+    /*
+        A function to create a transition matrix from lists of words.
+        Heuristic states that the next word is dependent on the previous `order` words.
+        Each context keeps its distinct successors with how often they follow it,
+        every occurrence counting as many times as the weight of its document.
+        With boundaries, the end of a line is a successor like any other word, and
+        contexts never reach back past it.
+    */
+
+    let mut counts: HashMap<Context, HashMap<WordId, u64>> = HashMap::new();
+    for (words, &weight) in documents.iter().zip(weights) {
+        for window in padded(words, order, boundary).windows(order + 1) {
+            let mut context = Context::new(window[..order].to_vec());
+            context.mark_boundary(boundary);
+            *counts
+                .entry(context)
+                .or_default()
+                .entry(window[order])
+                .or_insert(0) += weight as u64;
+        }
+    }
+    counts
+        .into_iter()
+        .map(|(context, successors)| {
+            let successors = successors
+                .into_iter()
+                .map(|(word, count)| (word, saturating_count(count)));
+            (context, Distribution::from_counts(successors))
+        })
+        .collect()
+}
+
+fn position_starts(
+    documents: &[Vec<WordId>],
+    weights: &[u32],
+    order: usize,
+    boundary: Option<WordId>,
+) -> HashMap<Context, u64> {
+    /*
+        The start states at every position of the training text. Position i of a
+        document contributes the context words[i..i + order], for every i that leaves
+        a following word, so the first context of a document is a start state too.
+        With boundaries, contexts that end at the end of a line are skipped: the
+        passphrase opens with the last word of its start context.
+    */
+    let mut starts: HashMap<Context, u64> = HashMap::new();
+    for (words, &weight) in documents.iter().zip(weights) {
+        let words = padded(words, order, boundary);
+        if words.len() < min_corpus_words(order) {
+            continue;
+        }
+        for i in 0..words.len() - order {
+            let mut context = Context::new(words[i..i + order].to_vec());
+            context.mark_boundary(boundary);
+            if boundary.is_none() || context.last() != boundary {
+                *starts.entry(context).or_insert(0) += weight as u64;
+            }
+        }
+    }
+    starts
+}
+
+pub(crate) fn first_words(starts: &Distribution<Context>) -> Distribution<Option<WordId>> {
+    /*
+        The start states grouped by the word the passphrase opens with, the last word
+        of the context. The words before it shape the next draws but never appear in
+        the passphrase, so they are not a secret an attacker has to guess. An order 0
+        context opens with no word, and the first word is drawn like any other.
+    */
+    let mut counts: HashMap<Option<WordId>, u64> = HashMap::new();
+    for (context, count) in starts.iter() {
+        *counts.entry(context.last()).or_insert(0) += count as u64;
+    }
+    Distribution::from_counts(
+        counts
+            .into_iter()
+            .map(|(word, count)| (word, saturating_count(count))),
+    )
+}
+
+pub(crate) fn line_starts(
+    transitions: &HashMap<Context, Distribution<WordId>>,
+    order: usize,
+    boundary: Option<WordId>,
+) -> Distribution<Context> {
+    /*
+        The start states at the first word of a line or sentence, weighted by how many
+        lines open with it. They are read off the successors of the context made of
+        boundary markers alone, so models without boundaries have none. An order 0
+        model has no context to start from and draws its first word like any other.
+    */
+    let opening = match boundary {
+        Some(boundary) => Context::new(vec![boundary; order]),
+        None => return Distribution::from_counts(Vec::new()),
+    };
+    match transitions.get(&opening) {
+        Some(_) if order == 0 => Distribution::from_counts([(opening, 1)]),
+        Some(first_words) => Distribution::from_counts(first_words.iter().map(|(&word, count)| {
+            let mut context = opening.clone();
+            context.shift(word);
+            (context, count)
+        })),
+        None => Distribution::from_counts(Vec::new()),
+    }
+}
+
+pub struct TransitionModel {
+    pub(crate) order: usize,
+    pub(crate) corpus_hash: u64,
+    pub(crate) tokenizer: Tokenizer,
+    pub(crate) vocabulary: Vocabulary,
+    pub(crate) transitions: HashMap<Context, Distribution<WordId>>,
+    /*
+        The lower-order tables used for backoff: backoff[k] is trained like transitions
+        but with k words of context, for every k below the model order. Models saved
+        before format version 3 have none.
+    */
+    pub(crate) backoff: Vec<HashMap<Context, Distribution<WordId>>>,
+    /* The start states at every position of the training text, see position_starts. */
+    pub(crate) starts: Distribution<Context>,
+    /* The start states at the first word of a line, empty without boundaries. */
+    pub(crate) line_starts: Distribution<Context>,
+    /* The training text as word ids, split at filtered tokens, kept for the verbatim check. */
+    pub(crate) documents: Vec<Vec<WordId>>,
+    /*
+        For a model trained with weights, the count a source of weight 1 adds up to,
+        see Corpus::weight_scale. Models saved before format version 4 have none.
+    */
+    pub(crate) weight_scale: Option<f64>,
+}
+
+impl TransitionModel {
+    pub fn train(corpus: &Corpus, order: usize) -> Result<TransitionModel, PassphraseError> {
+        /*
+            A function to train a transition model of the given order on a corpus.
+            The order is the number of previous words the next word depends on:
+            0 ignores context entirely (a unigram model, the most entropy per word),
+            2 conditions on two words, and higher orders read more like the corpus.
+        */
+        if order > MAX_ORDER {
+            return Err(PassphraseError::InvalidOrder(format!(
+                "an n-gram order of {order} is not supported, the maximum is {MAX_ORDER}"
+            )));
+        }
+        let model = TransitionModel::count(corpus, order);
+        if model.starts.is_empty() {
+            return Err(PassphraseError::CorpusTooSmall {
+                words: model.word_count(),
+                required: match model.boundary() {
+                    Some(_) => 1,
+                    None => min_corpus_words(order),
+                },
+            });
+        }
+        Ok(model)
+    }
+
+    pub(crate) fn count(corpus: &Corpus, order: usize) -> TransitionModel {
+        /*
+            A function to count the transitions and start states of a corpus, which
+            may be too small to start a chain from.
+        */
+        let mut vocabulary = Vocabulary::new();
+        /* Sources with a weight of zero are left out entirely. */
+        let (segments, weights): (Vec<Vec<String>>, Vec<u32>) = corpus
+            .weighted_segments()
+            .into_iter()
+            .filter(|(_, weight)| *weight > 0)
+            .unzip();
+        let documents = segments
+            .iter()
+            .map(|words| {
+                words
+                    .iter()
+                    .map(|word| vocabulary.intern(word))
+                    .collect::<Vec<WordId>>()
+            })
+            .collect::<Vec<Vec<WordId>>>();
+        let boundary = vocabulary.id(BOUNDARY);
+        let transitions = create_transition_matrix(&documents, &weights, order, boundary);
+        let starts = position_starts(&documents, &weights, order, boundary);
+        TransitionModel {
+            order,
+            corpus_hash: corpus.fingerprint(),
+            tokenizer: *corpus.tokenizer(),
+            line_starts: line_starts(&transitions, order, boundary),
+            transitions,
+            backoff: (0..order)
+                .map(|k| create_transition_matrix(&documents, &weights, k, boundary))
+                .collect(),
+            vocabulary,
+            starts: Distribution::from_counts(
+                starts
+                    .into_iter()
+                    .map(|(context, count)| (context, saturating_count(count))),
+            ),
+            documents,
+            weight_scale: corpus.weight_scale(),
+        }
+    }
+
+    pub fn order(&self) -> usize {
+        self.order
+    }
+
+    pub fn corpus_hash(&self) -> u64 {
+        self.corpus_hash
+    }
+
+    pub fn tokenizer(&self) -> &Tokenizer {
+        &self.tokenizer
+    }
+
+    pub fn vocabulary(&self) -> &Vocabulary {
+        &self.vocabulary
+    }
+
+    pub fn documents(&self) -> &[Vec<WordId>] {
+        &self.documents
+    }
+
+    pub fn boundary(&self) -> Option<WordId> {
+        /*
+            The id of the BOUNDARY marker, if the model was trained with line or
+            sentence boundaries. The marker is never part of a passphrase.
+        */
+        self.vocabulary.id(BOUNDARY)
+    }
+
+    pub fn word_count(&self) -> usize {
+        /* The number of words in the training text, not counting boundary markers. */
+        let boundary = self.boundary();
+        self.documents
+            .iter()
+            .flatten()
+            .filter(|&&word| Some(word) != boundary)
+            .count()
+    }
+
+    pub fn context_count(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn has_backoff(&self) -> bool {
+        self.backoff.len() == self.order
+    }
+
+    pub fn successors(&self, context: &Context) -> Option<&Distribution<WordId>> {
+        /*
+            Looks a context up in the table for its length, so shorter contexts are
+            answered by the lower-order backoff tables.
+        */
+        match context.words().len() {
+            k if k == self.order => self.transitions.get(context),
+            k => self.backoff.get(k)?.get(context),
+        }
+    }
+
+    pub fn starts(&self) -> &Distribution<Context> {
+        &self.starts
+    }
+
+    pub fn line_starts(&self) -> &Distribution<Context> {
+        &self.line_starts
+    }
+
+    pub fn start_entropy(&self) -> f64 {
+        /*
+            The entropy of the first word of a passphrase started at a random position,
+            weighted by how many positions open with each word, see first_words.
+        */
+        first_words(&self.starts).entropy(SamplingMode::Weighted)
+    }
+
+    pub fn describe(&self, context: &Context) -> String {
+        context
+            .words()
+            .iter()
+            .map(|&id| self.vocabulary.word(id))
+            .collect::<Vec<&str>>()
+            .join(" ")
+    }
+}
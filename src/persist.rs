@@ -0,0 +1,438 @@
+/*
+    Saving and loading trained transition models.
+
+    A model file is laid out as follows, with all integers little-endian:
+
+        magic        4 bytes, "MKPG"
+        version      u16
+        order        u16
+        corpus hash  u64, see Corpus::fingerprint
+        tokenizer    u32 length + UTF-8 bytes
+        vocabulary   u32 count, then per word: u32 length + UTF-8 bytes
+        transitions  u32 count, then per context: `order` u32 word ids,
+                     u32 successor count, then per successor: u32 word id + u32 count
+        starts       u32 count, then per start context: `order` u32 word ids + u32 count
+                     (the start states at every position; line starts are not stored,
+                     they are read off the transitions on load)
+        documents    u32 count, then per document: u32 length + that many u32 word ids
+                     (version 2 onwards; the training text used by the verbatim check)
+        backoff      for every lower order k from 0 to order - 1, a table laid out like
+                     transitions with k word ids per context (version 3 onwards)
+        weight scale f64, the count a source of weight 1 adds up to, or 0 for a model
+                     trained without weights (version 4 onwards)
+        checksum     u64, FNV-1a of every preceding byte
+
+    Words are stored once in the vocabulary and referred to by their id, and
+    entries are sorted so that the same model always produces the same file.
+*/
+
+use crate::distribution::Distribution;
+use crate::error::PassphraseError;
+use crate::hash::Fnv1a;
+use crate::model::{line_starts, Context, TransitionModel, MAX_ORDER};
+use crate::tokenizer::{Tokenizer, BOUNDARY};
+use crate::vocabulary::{Vocabulary, WordId};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"MKPG";
+pub const FORMAT_VERSION: u16 = 4;
+/* The oldest format version that can still be read. */
+const MIN_FORMAT_VERSION: u16 = 1;
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: usize) {
+        self.bytes.extend_from_slice(&(value as u32).to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn str(&mut self, value: &str) {
+        self.u32(value.len());
+        self.bytes.extend_from_slice(value.as_bytes());
+    }
+
+    fn context(&mut self, context: &Context) {
+        for word in context.words() {
+            self.u32(word.0 as usize);
+        }
+    }
+
+    fn table(&mut self, table: &HashMap<Context, Distribution<WordId>>) {
+        let table = table.iter().collect::<BTreeMap<_, _>>();
+        self.u32(table.len());
+        for (context, successors) in table {
+            self.context(context);
+            self.u32(successors.len());
+            for (word, count) in successors.iter() {
+                self.u32(word.0 as usize);
+                self.u32(count as usize);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], PassphraseError> {
+        if self.bytes.len() - self.position < len {
+            return Err(invalid("the file is truncated"));
+        }
+        let slice = &self.bytes[self.position..self.position + len];
+        self.position += len;
+        Ok(slice)
+    }
+
+    fn u16(&mut self) -> Result<u16, PassphraseError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<usize, PassphraseError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as usize)
+    }
+
+    fn u64(&mut self) -> Result<u64, PassphraseError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String, PassphraseError> {
+        let len = self.u32()?;
+        String::from_utf8(self.take(len)?.to_vec())
+            .map_err(|_| invalid("a word is not valid UTF-8"))
+    }
+
+    fn word(&mut self, vocabulary: &Vocabulary) -> Result<WordId, PassphraseError> {
+        let id = self.u32()?;
+        if id >= vocabulary.len() {
+            return Err(invalid("a word id is outside the vocabulary"));
+        }
+        Ok(WordId(id as u32))
+    }
+
+    fn count(&mut self) -> Result<u32, PassphraseError> {
+        match self.u32()? {
+            0 => Err(invalid("a count is zero")),
+            count => Ok(count as u32),
+        }
+    }
+
+    fn context(
+        &mut self,
+        order: usize,
+        vocabulary: &Vocabulary,
+    ) -> Result<Context, PassphraseError> {
+        (0..order)
+            .map(|_| self.word(vocabulary))
+            .collect::<Result<Vec<WordId>, PassphraseError>>()
+            .map(Context::new)
+    }
+
+    fn table(
+        &mut self,
+        order: usize,
+        vocabulary: &Vocabulary,
+    ) -> Result<HashMap<Context, Distribution<WordId>>, PassphraseError> {
+        let mut table = HashMap::new();
+        for _ in 0..self.u32()? {
+            let context = self.context(order, vocabulary)?;
+            let successors = (0..self.u32()?)
+                .map(|_| Ok((self.word(vocabulary)?, self.count()?)))
+                .collect::<Result<Vec<(WordId, u32)>, PassphraseError>>()?;
+            if successors.is_empty() {
+                return Err(invalid("a context has no successors"));
+            }
+            table.insert(context, Distribution::from_counts(successors));
+        }
+        Ok(table)
+    }
+}
+
+fn invalid(reason: &str) -> PassphraseError {
+    PassphraseError::InvalidModel(reason.to_string())
+}
+
+impl TransitionModel {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        /*
+            A function to serialize the model into the versioned binary format above.
+        */
+        let mut out = Writer { bytes: Vec::new() };
+        out.bytes.extend_from_slice(MAGIC);
+        out.u16(FORMAT_VERSION);
+        out.u16(self.order() as u16);
+        out.u64(self.corpus_hash);
+        out.str(&self.tokenizer.to_string());
+        out.u32(self.vocabulary.len());
+        for word in self.vocabulary.words() {
+            out.str(word);
+        }
+        out.table(&self.transitions);
+        out.u32(self.starts.len());
+        for (context, count) in self.starts.iter() {
+            out.context(context);
+            out.u32(count as usize);
+        }
+        out.u32(self.documents.len());
+        for words in &self.documents {
+            out.u32(words.len());
+            for word in words {
+                out.u32(word.0 as usize);
+            }
+        }
+        for table in &self.backoff {
+            out.table(table);
+        }
+        out.u64(self.weight_scale.unwrap_or(0.0).to_bits());
+        let mut hasher = Fnv1a::new();
+        hasher.write(&out.bytes);
+        out.u64(hasher.finish());
+        out.bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<TransitionModel, PassphraseError> {
+        /*
+            A function to deserialize a model, rejecting files with the wrong magic,
+            an unsupported format version, or a checksum that does not match.
+        */
+        let mut input = Reader { bytes, position: 0 };
+        if input.take(MAGIC.len())? != MAGIC {
+            return Err(invalid("the file is not a passphrase model"));
+        }
+        let version = input.u16()?;
+        if !(MIN_FORMAT_VERSION..=FORMAT_VERSION).contains(&version) {
+            return Err(PassphraseError::InvalidModel(format!(
+                "format version {version} is not supported, expected {MIN_FORMAT_VERSION} to {FORMAT_VERSION}"
+            )));
+        }
+        if bytes.len() < input.position + 8 {
+            return Err(invalid("the file is truncated"));
+        }
+        let (contents, checksum) = bytes.split_at(bytes.len() - 8);
+        let mut hasher = Fnv1a::new();
+        hasher.write(contents);
+        if hasher.finish().to_le_bytes() != checksum {
+            return Err(invalid(
+                "the checksum does not match, the file is corrupted or truncated",
+            ));
+        }
+        input.bytes = contents;
+
+        let order = input.u16()? as usize;
+        if order > MAX_ORDER {
+            return Err(PassphraseError::InvalidOrder(format!(
+                "an n-gram order of {order} is not supported, the maximum is {MAX_ORDER}"
+            )));
+        }
+        let corpus_hash = input.u64()?;
+        let tokenizer = input
+            .str()?
+            .parse::<Tokenizer>()
+            .map_err(|error| invalid(&format!("the tokenizer settings are invalid: {error}")))?;
+        let mut vocabulary = Vocabulary::new();
+        for i in 0..input.u32()? {
+            vocabulary.intern(&input.str()?);
+            if vocabulary.len() != i + 1 {
+                return Err(invalid("the vocabulary has a duplicate word"));
+            }
+        }
+        let transitions = input.table(order, &vocabulary)?;
+        let starts = (0..input.u32()?)
+            .map(|_| Ok((input.context(order, &vocabulary)?, input.count()?)))
+            .collect::<Result<Vec<(Context, u32)>, PassphraseError>>()?;
+        let mut documents = Vec::new();
+        if version >= 2 {
+            for _ in 0..input.u32()? {
+                documents.push(
+                    (0..input.u32()?)
+                        .map(|_| input.word(&vocabulary))
+                        .collect::<Result<Vec<WordId>, PassphraseError>>()?,
+                );
+            }
+        }
+        let mut backoff = Vec::new();
+        if version >= 3 {
+            for k in 0..order {
+                backoff.push(input.table(k, &vocabulary)?);
+            }
+        }
+        let mut weight_scale = None;
+        if version >= 4 {
+            let scale = f64::from_bits(input.u64()?);
+            if !(scale.is_finite() && scale >= 0.0) {
+                return Err(invalid("the weight scale is not a non-negative number"));
+            }
+            weight_scale = (scale > 0.0).then_some(scale);
+        }
+        if input.position != contents.len() {
+            return Err(invalid("the file has trailing data"));
+        }
+        if starts.is_empty() {
+            return Err(invalid("the model has no start states"));
+        }
+        let boundary = vocabulary.id(BOUNDARY);
+        Ok(TransitionModel {
+            order,
+            corpus_hash,
+            tokenizer,
+            line_starts: line_starts(&transitions, order, boundary),
+            vocabulary,
+            transitions,
+            starts: Distribution::from_counts(starts),
+            documents,
+            backoff,
+            weight_scale,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), PassphraseError> {
+        let path = path.as_ref();
+        fs::write(path, self.to_bytes()).map_err(|source| PassphraseError::ModelIo {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<TransitionModel, PassphraseError> {
+        let path = path.as_ref();
+        let bytes = fs::read(path).map_err(|source| PassphraseError::ModelIo {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        TransitionModel::from_bytes(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::Corpus;
+
+    fn model() -> TransitionModel {
+        let corpus = Corpus::from_text(
+            "the quick brown fox jumps over the lazy dog and the quick red fox runs",
+        );
+        TransitionModel::train(&corpus, 2).unwrap()
+    }
+
+    fn sealed(mut out: Writer) -> Vec<u8> {
+        /* A function to append the checksum of everything written so far. */
+        let mut hasher = Fnv1a::new();
+        hasher.write(&out.bytes);
+        out.u64(hasher.finish());
+        out.bytes
+    }
+
+    fn legacy(model: &TransitionModel, version: u16) -> Vec<u8> {
+        /*
+            A function to write a model in an older format version, which stops after
+            the starts (version 1) or the documents (version 2).
+        */
+        let mut out = Writer { bytes: Vec::new() };
+        out.bytes.extend_from_slice(MAGIC);
+        out.u16(version);
+        out.u16(model.order() as u16);
+        out.u64(model.corpus_hash);
+        out.str(&model.tokenizer.to_string());
+        out.u32(model.vocabulary.len());
+        for word in model.vocabulary.words() {
+            out.str(word);
+        }
+        out.table(&model.transitions);
+        out.u32(model.starts.len());
+        for (context, count) in model.starts.iter() {
+            out.context(context);
+            out.u32(count as usize);
+        }
+        if version >= 2 {
+            out.u32(model.documents.len());
+            for words in &model.documents {
+                out.u32(words.len());
+                for word in words {
+                    out.u32(word.0 as usize);
+                }
+            }
+        }
+        sealed(out)
+    }
+
+    fn reason(result: Result<TransitionModel, PassphraseError>) -> String {
+        match result {
+            Err(PassphraseError::InvalidModel(reason)) => reason,
+            Err(error) => panic!("expected an invalid model, got {error}"),
+            Ok(_) => panic!("expected an invalid model, got a model"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let model = model();
+        let bytes = model.to_bytes();
+        let loaded = TransitionModel::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.order(), model.order());
+        assert_eq!(loaded.corpus_hash(), model.corpus_hash());
+        assert_eq!(loaded.vocabulary().words(), model.vocabulary().words());
+        assert_eq!(loaded.documents(), model.documents());
+        assert_eq!(loaded.context_count(), model.context_count());
+        assert!(loaded.has_backoff());
+        assert_eq!(loaded.to_bytes(), bytes);
+    }
+
+    #[test]
+    fn truncated_file_is_rejected() {
+        let bytes = model().to_bytes();
+        for len in [0, 3, 6, bytes.len() / 2, bytes.len() - 1] {
+            assert!(reason(TransitionModel::from_bytes(&bytes[..len])).contains("truncated"));
+        }
+    }
+
+    #[test]
+    fn bad_checksum_is_rejected() {
+        let mut bytes = model().to_bytes();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 1;
+        assert!(reason(TransitionModel::from_bytes(&bytes)).contains("checksum"));
+    }
+
+    #[test]
+    fn unsupported_version_is_rejected() {
+        let mut bytes = model().to_bytes();
+        bytes.truncate(bytes.len() - 8);
+        bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        let bytes = sealed(Writer { bytes });
+        assert!(reason(TransitionModel::from_bytes(&bytes)).contains("not supported"));
+    }
+
+    #[test]
+    fn version_1_loads_without_documents_or_backoff() {
+        let model = model();
+        let loaded = TransitionModel::from_bytes(&legacy(&model, 1)).unwrap();
+        assert_eq!(loaded.vocabulary().words(), model.vocabulary().words());
+        assert_eq!(loaded.context_count(), model.context_count());
+        assert!(loaded.documents().is_empty());
+        assert!(!loaded.has_backoff());
+    }
+
+    #[test]
+    fn version_2_loads_with_documents_and_without_backoff() {
+        let model = model();
+        let loaded = TransitionModel::from_bytes(&legacy(&model, 2)).unwrap();
+        assert_eq!(loaded.documents(), model.documents());
+        assert!(!loaded.has_backoff());
+        assert_eq!(loaded.weight_scale, None);
+    }
+}
@@ -0,0 +1,416 @@
+/*
+    Password policies: formatting a passphrase so that it meets the rules of a system.
+
+    A policy joins the words with a separator, capitalizes them, and appends random
+    digits and symbols. Every random choice it makes is uniform and counted in the
+    entropy, so the attacker is assumed to know the policy but not the choices.
+    Passphrases that still break the length or character class rules are rejected and
+    generated again.
+*/
+
+use crate::entropy::Entropy;
+use crate::error::PassphraseError;
+use crate::generator::{capitalize, Capitalization};
+use rand::{CryptoRng, Rng};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const BUILTIN_POLICIES: [&str; 2] = ["ad-default", "banking"];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Casing {
+    Fixed(Capitalization),
+    /*
+        Capitalize the first letter of every word or not, at random: one bit per word
+        whose first character has a case.
+    */
+    Random,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CharClass {
+    Lower,
+    Upper,
+    Digit,
+    Symbol,
+}
+
+impl CharClass {
+    pub const ALL: [CharClass; 4] = [
+        CharClass::Lower,
+        CharClass::Upper,
+        CharClass::Digit,
+        CharClass::Symbol,
+    ];
+
+    fn matches(&self, c: char) -> bool {
+        match self {
+            CharClass::Lower => c.is_lowercase(),
+            CharClass::Upper => c.is_uppercase(),
+            CharClass::Digit => c.is_numeric(),
+            CharClass::Symbol => !c.is_alphanumeric() && !c.is_whitespace(),
+        }
+    }
+}
+
+impl fmt::Display for CharClass {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            CharClass::Lower => "lower",
+            CharClass::Upper => "upper",
+            CharClass::Digit => "digit",
+            CharClass::Symbol => "symbol",
+        };
+        write!(f, "{name}")
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub name: String,
+    /* One of these is picked at random to join the words. */
+    pub separators: Vec<String>,
+    pub casing: Casing,
+    /* The number of random digits appended, as one block, to a random word. */
+    pub digits: usize,
+    /* The number of random symbols from symbol_set appended, as one block, to a random word. */
+    pub symbols: usize,
+    pub symbol_set: Vec<char>,
+    /* The length of the formatted passphrase in characters. */
+    pub min_length: usize,
+    pub max_length: Option<usize>,
+    /* Character classes that must all appear. */
+    pub required: Vec<CharClass>,
+    /* The number of the four character classes that must appear, whichever they are. */
+    pub min_classes: usize,
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy {
+            name: "custom".to_string(),
+            separators: vec![" ".to_string()],
+            casing: Casing::Fixed(Capitalization::Lower),
+            digits: 0,
+            symbols: 0,
+            symbol_set: "!@#$%&*?-_+=".chars().collect(),
+            min_length: 0,
+            max_length: None,
+            required: Vec::new(),
+            min_classes: 0,
+        }
+    }
+}
+
+fn invalid(reason: &str) -> PassphraseError {
+    PassphraseError::InvalidPolicy(reason.to_string())
+}
+
+impl Policy {
+    pub fn builtin(name: &str) -> Option<Policy> {
+        /*
+            The built-in profiles. "ad-default" follows the default Active Directory
+            complexity rules: at least 7 characters and three of the four character
+            classes. "banking" follows the stricter rules common on banking sites:
+            8 to 20 characters with every class.
+        */
+        match name {
+            "ad-default" => Some(Policy {
+                name: name.to_string(),
+                separators: vec!["-".to_string()],
+                casing: Casing::Fixed(Capitalization::Title),
+                digits: 1,
+                min_length: 7,
+                min_classes: 3,
+                ..Policy::default()
+            }),
+            "banking" => Some(Policy {
+                name: name.to_string(),
+                separators: vec![String::new()],
+                casing: Casing::Fixed(Capitalization::Title),
+                digits: 2,
+                symbols: 1,
+                symbol_set: "!@#$%&*?".chars().collect(),
+                min_length: 8,
+                max_length: Some(20),
+                required: CharClass::ALL.to_vec(),
+                ..Policy::default()
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Policy, PassphraseError> {
+        let path = path.as_ref();
+        let text = fs::read_to_string(path).map_err(|error| {
+            PassphraseError::InvalidPolicy(format!("could not read {}: {error}", path.display()))
+        })?;
+        let mut policy = text.parse::<Policy>()?;
+        if policy.name == Policy::default().name {
+            policy.name = path.display().to_string();
+        }
+        Ok(policy)
+    }
+
+    fn guaranteed(&self, class: CharClass) -> bool {
+        /*
+            Whether every passphrase formatted with the policy has a character of the
+            class, assuming its words have letters.
+        */
+        match class {
+            CharClass::Lower => self.casing != Casing::Fixed(Capitalization::Upper),
+            CharClass::Upper => match self.casing {
+                Casing::Fixed(capitalization) => capitalization != Capitalization::Lower,
+                Casing::Random => self.required.contains(&CharClass::Upper),
+            },
+            CharClass::Digit => self.digits > 0,
+            CharClass::Symbol => {
+                self.symbols > 0
+                    || self
+                        .separators
+                        .iter()
+                        .all(|separator| separator.chars().any(|c| class.matches(c)))
+            }
+        }
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), PassphraseError> {
+        /*
+            Rejects policies whose random choices could never meet their own rules.
+        */
+        if self.separators.is_empty() {
+            return Err(invalid("at least one separator is needed"));
+        }
+        if self.symbols > 0 && self.symbol_set.is_empty() {
+            return Err(invalid("symbols are appended, but the symbol set is empty"));
+        }
+        if self.max_length.is_some_and(|max| max < self.min_length) {
+            return Err(invalid("the maximum length is below the minimum length"));
+        }
+        if let Some(class) = self.required.iter().find(|&&class| !self.guaranteed(class)) {
+            return Err(PassphraseError::InvalidPolicy(format!(
+                "{} requires {class} characters, but its casing, digits and symbols do not always add one",
+                self.name
+            )));
+        }
+        let guaranteed = CharClass::ALL
+            .iter()
+            .filter(|&&class| self.guaranteed(class))
+            .count();
+        if self.min_classes > guaranteed {
+            return Err(PassphraseError::InvalidPolicy(format!(
+                "{} requires {} character classes, but only adds {guaranteed}",
+                self.name, self.min_classes
+            )));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn apply<R: Rng + CryptoRng + ?Sized>(
+        &self,
+        rng: &mut R,
+        words: &[String],
+    ) -> (String, Entropy) {
+        /*
+            A function to format the words of a passphrase, returning the result with the
+            entropy of the random choices made. With random casing and a required
+            capital, the choice is uniform among the combinations with at least one.
+        */
+        let mut entropy = Entropy::default();
+        let mut words = match self.casing {
+            Casing::Fixed(capitalization) => words
+                .iter()
+                .map(|word| capitalize(word, capitalization))
+                .collect::<Vec<String>>(),
+            Casing::Random => {
+                /*
+                    Only the words that capitalizing changes are chosen for: "'cause" or
+                    "2pac" look the same either way, and add no entropy.
+                */
+                let cased = words
+                    .iter()
+                    .map(|word| {
+                        capitalize(word, Capitalization::Title)
+                            != capitalize(word, Capitalization::Lower)
+                    })
+                    .collect::<Vec<bool>>();
+                let count = cased.iter().filter(|&&cased| cased).count();
+                let mut capitals =
+                    if self.required.contains(&CharClass::Upper) && (1..64).contains(&count) {
+                        /* Uniform among the 2^n - 1 choices that capitalize at least one word. */
+                        let mask = rng.gen_range(1..1u64 << count);
+                        entropy += Entropy::uniform((1usize << count) - 1);
+                        (0..count)
+                            .map(|i| mask >> i & 1 == 1)
+                            .collect::<Vec<bool>>()
+                    } else {
+                        entropy += (0..count).map(|_| Entropy::uniform(2)).sum();
+                        (0..count).map(|_| rng.gen::<bool>()).collect()
+                    }
+                    .into_iter();
+                words
+                    .iter()
+                    .zip(cased)
+                    .map(
+                        |(word, cased)| match cased && capitals.next() == Some(true) {
+                            true => capitalize(word, Capitalization::Title),
+                            false => capitalize(word, Capitalization::Lower),
+                        },
+                    )
+                    .collect()
+            }
+        };
+        let digits = ('0'..='9').collect::<Vec<char>>();
+        for (count, set) in [(self.digits, &digits), (self.symbols, &self.symbol_set)] {
+            if count == 0 {
+                continue;
+            }
+            let block = (0..count)
+                .map(|_| set[rng.gen_range(0..set.len())])
+                .collect::<String>();
+            /* Without words to append it to, the block stands alone. */
+            if words.is_empty() {
+                words.push(String::new());
+            }
+            let word = rng.gen_range(0..words.len());
+            words[word].push_str(&block);
+            entropy += (0..count)
+                .map(|_| Entropy::uniform(set.len()))
+                .sum::<Entropy>()
+                + Entropy::uniform(words.len());
+        }
+        let separator = &self.separators[rng.gen_range(0..self.separators.len())];
+        entropy += Entropy::uniform(self.separators.len());
+        (words.join(separator), entropy)
+    }
+
+    pub fn accepts(&self, phrase: &str) -> bool {
+        /*
+            Whether a formatted passphrase meets the length and character class rules.
+        */
+        let length = phrase.chars().count();
+        let present = |class: &CharClass| phrase.chars().any(|c| class.matches(c));
+        length >= self.min_length
+            && self.max_length.is_none_or(|max| length <= max)
+            && self.required.iter().all(present)
+            && CharClass::ALL.iter().filter(|class| present(class)).count() >= self.min_classes
+    }
+}
+
+impl FromStr for Policy {
+    type Err = PassphraseError;
+
+    fn from_str(text: &str) -> Result<Policy, PassphraseError> {
+        /*
+            A function to parse a policy file of "key = value" lines. Lines starting
+            with # are comments.
+            Settings that are not listed keep the defaults: words joined with a space,
+            lowercase, nothing appended and no rules.
+                name = intranet
+                separators = - . _   (space and none stand for " " and "")
+                case = lower | upper | title | random
+                digits = 2
+                symbols = 1
+                symbol-set = !@#$%
+                min-length = 12
+                max-length = 64
+                require = lower upper digit symbol
+                min-classes = 3
+        */
+        let mut policy = Policy::default();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (key, value) = line
+                .split_once('=')
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .ok_or_else(|| {
+                    PassphraseError::InvalidPolicy(format!(
+                        "expected \"key = value\", got \"{line}\""
+                    ))
+                })?;
+            let count = || {
+                value.parse::<usize>().map_err(|_| {
+                    PassphraseError::InvalidPolicy(format!(
+                        "{key} must be a non-negative integer, got \"{value}\""
+                    ))
+                })
+            };
+            match key {
+                "name" => policy.name = value.to_string(),
+                "separators" => {
+                    policy.separators.clear();
+                    for separator in value.split_whitespace() {
+                        let separator = match separator {
+                            "space" => " ".to_string(),
+                            "none" => String::new(),
+                            separator => separator.to_string(),
+                        };
+                        if !policy.separators.contains(&separator) {
+                            policy.separators.push(separator);
+                        }
+                    }
+                }
+                "case" => {
+                    policy.casing = match value {
+                        "lower" => Casing::Fixed(Capitalization::Lower),
+                        "upper" => Casing::Fixed(Capitalization::Upper),
+                        "title" => Casing::Fixed(Capitalization::Title),
+                        "random" => Casing::Random,
+                        _ => return Err(invalid("case must be lower, upper, title or random")),
+                    }
+                }
+                "digits" => policy.digits = count()?,
+                "symbols" => policy.symbols = count()?,
+                "symbol-set" => {
+                    policy.symbol_set = value.chars().filter(|c| !c.is_whitespace()).collect();
+                    policy.symbol_set.sort_unstable();
+                    policy.symbol_set.dedup();
+                }
+                "min-length" => policy.min_length = count()?,
+                "max-length" => policy.max_length = Some(count()?),
+                "require" => {
+                    policy.required = value
+                        .split_whitespace()
+                        .map(|class| {
+                            CharClass::ALL
+                                .into_iter()
+                                .find(|known| known.to_string() == class)
+                                .ok_or_else(|| {
+                                    PassphraseError::InvalidPolicy(format!(
+                                        "unknown character class \"{class}\", expected lower, upper, digit or symbol"
+                                    ))
+                                })
+                        })
+                        .collect::<Result<Vec<CharClass>, PassphraseError>>()?
+                }
+                "min-classes" => policy.min_classes = count()?,
+                _ => {
+                    return Err(PassphraseError::InvalidPolicy(format!(
+                        "unknown setting \"{key}\""
+                    )))
+                }
+            }
+        }
+        Ok(policy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+
+    #[test]
+    fn apply_to_no_words() {
+        let policy = Policy::builtin("ad-default").unwrap();
+        let mut rng = ChaCha20Rng::seed_from_u64(4);
+        let (phrase, _) = policy.apply(&mut rng, &[]);
+        assert!(phrase.chars().any(|c| c.is_ascii_digit()));
+    }
+}
@@ -0,0 +1,65 @@
+/*
+    The randomness sources used to generate passphrases.
+*/
+
+use rand::rngs::OsRng;
+use rand::{CryptoRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+pub enum EntropySource {
+    /* The operating system CSPRNG, used unless a seed is given. */
+    Os(OsRng),
+    /* A reproducible ChaCha20 stream. Anyone who knows the seed can regenerate the output. */
+    Seeded(Box<ChaCha20Rng>),
+}
+
+impl EntropySource {
+    pub fn from_seed(seed: Option<u64>) -> EntropySource {
+        match seed {
+            Some(seed) => EntropySource::Seeded(Box::new(ChaCha20Rng::seed_from_u64(seed))),
+            None => EntropySource::Os(OsRng),
+        }
+    }
+
+    pub fn is_insecure(&self) -> bool {
+        matches!(self, EntropySource::Seeded(_))
+    }
+}
+
+impl Default for EntropySource {
+    fn default() -> EntropySource {
+        EntropySource::Os(OsRng)
+    }
+}
+
+impl RngCore for EntropySource {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            EntropySource::Os(rng) => rng.next_u32(),
+            EntropySource::Seeded(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            EntropySource::Os(rng) => rng.next_u64(),
+            EntropySource::Seeded(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            EntropySource::Os(rng) => rng.fill_bytes(dest),
+            EntropySource::Seeded(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            EntropySource::Os(rng) => rng.try_fill_bytes(dest),
+            EntropySource::Seeded(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+impl CryptoRng for EntropySource {}
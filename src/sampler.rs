@@ -0,0 +1,637 @@
+/*
+    Walking the transition model to produce a chain of words.
+*/
+
+use crate::distribution::Distribution;
+use crate::entropy::{Entropy, SamplingMode};
+use crate::generator::{DeadEndPolicy, Target, MAX_CHAIN_WORDS};
+use crate::include::{conditioned, Inclusion};
+use crate::model::{Context, TransitionModel};
+use crate::start::Starts;
+use crate::vocabulary::WordId;
+use rand::{CryptoRng, Rng};
+use std::collections::{BTreeMap, HashMap};
+
+pub(crate) struct ChainSettings {
+    pub(crate) target: Target,
+    pub(crate) dead_end: DeadEndPolicy,
+    pub(crate) sampling: SamplingMode,
+    /* Back off to a lower order when a context has fewer distinct successors than this. */
+    pub(crate) min_successors: Option<usize>,
+    /* Keep going past the target until the chain reaches the end of a line. */
+    pub(crate) end_at_boundary: bool,
+    pub(crate) start: Starts,
+    /* The start states after a dead end: the same as start, unless a first word was given. */
+    pub(crate) restart: Starts,
+    /* A word the passphrase must contain. */
+    pub(crate) include: Option<Inclusion>,
+    /* The most characters the passphrase may have, with the length of the separator. */
+    pub(crate) max_chars: Option<CharLimit>,
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct CharLimit {
+    pub(crate) limit: usize,
+    pub(crate) separator: usize,
+}
+
+pub(crate) struct Chain {
+    pub(crate) words: Vec<WordId>,
+    /* The entropy of the start state and every step, see markov_chain. */
+    pub(crate) entropy: Entropy,
+    pub(crate) start_entropy: Entropy,
+    /* How much less entropy the draws had than they would have without the character limit. */
+    pub(crate) length_reduction: Entropy,
+}
+
+pub(crate) enum ChainFailure {
+    /* The chain reached a context with no successors under DeadEndPolicy::Fail. */
+    DeadEnd(Context),
+    /* The chain grew past MAX_CHAIN_WORDS without reaching the target or a line end. */
+    TooLong,
+    /* The required word cannot be reached from the start states within the word count. */
+    Unreachable,
+    /* No word that could follow fits within the character limit. */
+    OverLength,
+}
+
+pub(crate) fn next_words<'m>(
+    model: &'m TransitionModel,
+    context: &Context,
+    min_successors: Option<usize>,
+) -> Option<&'m Distribution<WordId>> {
+    /*
+        A function to find the distribution the next word is drawn from. Without backoff
+        this is the full-order context. With backoff, it is the highest order whose context
+        has at least min_successors distinct successors; if no order has that many, the
+        highest order with any successors at all is used.
+    */
+    let min_successors = match min_successors {
+        Some(min_successors) => min_successors,
+        None => return model.successors(context),
+    };
+    let mut fallback = None;
+    for k in (0..=context.words().len()).rev() {
+        if let Some(next_words) = model.successors(&context.suffix(k)) {
+            if next_words.len() >= min_successors {
+                return Some(next_words);
+            }
+            fallback = fallback.or(Some(next_words));
+        }
+    }
+    fallback
+}
+
+fn fits(
+    model: &TransitionModel,
+    settings: &ChainSettings,
+    result: &[WordId],
+    word: Option<WordId>,
+) -> bool {
+    /*
+        Whether a word can be added to the passphrase without breaking the character
+        limit, leaving at least one character and a separator for every word still
+        needed to reach a word count. A boundary marker adds no characters.
+    */
+    let CharLimit { limit, separator } = match settings.max_chars {
+        Some(max_chars) => max_chars,
+        None => return true,
+    };
+    let word = match word {
+        Some(word) if Some(word) != model.boundary() => word,
+        _ => return true,
+    };
+    let length = |word: WordId| model.vocabulary().word(word).chars().count();
+    let used = result
+        .iter()
+        .map(|&word| length(word) + separator)
+        .sum::<usize>();
+    let needed = match settings.target {
+        Target::Words(words) => words
+            .saturating_sub(result.len() + 1)
+            .saturating_mul(1 + separator),
+        Target::MinBits(_) | Target::MinSurprisal(_) => 0,
+    };
+    used.saturating_add(length(word)).saturating_add(needed) <= limit
+}
+
+fn draw_start<R: Rng + CryptoRng + ?Sized>(
+    rng: &mut R,
+    model: &TransitionModel,
+    settings: &ChainSettings,
+    starts: &Starts,
+    result: &[WordId],
+    missing: Option<usize>,
+) -> Option<(Context, Option<WordId>, Entropy)> {
+    /*
+        A function to draw a start state with the word it opens with and the entropy of
+        the draw. While a required word is still missing, `missing` holds the words left
+        and the draw is conditioned on reaching it within them. With a character limit,
+        start states whose word does not fit are left out.
+    */
+    if settings.max_chars.is_some() {
+        return starts.pick_conditioned(rng, |_, first| {
+            match fits(model, settings, result, first) {
+                true => 1.0,
+                false => 0.0,
+            }
+        });
+    }
+    match settings.include.as_ref().zip(missing) {
+        Some((include, words)) => starts.pick_conditioned(rng, |context, first| {
+            include.after_start(model, settings, context, first, words)
+        }),
+        None => Some(starts.pick(rng)),
+    }
+}
+
+fn draw_next<R: Rng + CryptoRng + ?Sized>(
+    rng: &mut R,
+    model: &TransitionModel,
+    settings: &ChainSettings,
+    context: &Context,
+    next_words: &Distribution<WordId>,
+    result: &[WordId],
+    missing: Option<usize>,
+) -> Option<(WordId, Entropy)> {
+    /*
+        A function to draw the next word and the entropy of the draw, conditioned like
+        draw_start while a required word is still missing, or on fitting within the
+        character limit.
+    */
+    if settings.max_chars.is_some() {
+        let next_words = next_words
+            .probabilities(settings.sampling)
+            .map(|(&next_word, probability)| (next_word, probability))
+            .collect::<Vec<(WordId, f64)>>();
+        let weights = next_words
+            .iter()
+            .map(
+                |&(next_word, probability)| match fits(model, settings, result, Some(next_word)) {
+                    true => probability,
+                    false => 0.0,
+                },
+            )
+            .collect::<Vec<f64>>();
+        let (index, entropy) = conditioned(rng, &weights)?;
+        return Some((next_words[index].0, entropy));
+    }
+    match settings.include.as_ref().zip(missing) {
+        Some((include, words)) => {
+            let next_words = next_words
+                .probabilities(settings.sampling)
+                .map(|(&next_word, probability)| (next_word, probability))
+                .collect::<Vec<(WordId, f64)>>();
+            let weights = include.step_weights(model, settings, context, &next_words, words);
+            let (index, entropy) = conditioned(rng, &weights)?;
+            Some((next_words[index].0, entropy))
+        }
+        None => {
+            let next_word = *next_words.sample(rng, settings.sampling);
+            Some((next_word, next_words.drawn(&next_word, settings.sampling)))
+        }
+    }
+}
+
+pub(crate) fn markov_chain<R: Rng + CryptoRng + ?Sized>(
+    rng: &mut R,
+    model: &TransitionModel,
+    settings: &ChainSettings,
+) -> Result<Chain, ChainFailure> {
+    /*
+        A function to apply a Markov chain to generate the next word in a sequence.
+        Returns the word ids along with the total entropy, in bits, including the start state,
+        and the entropy of the start state alone.
+        The passphrase opens with the word picked along with the start context.
+        Each step counts the entropy of the distribution it actually drew from, which is a
+        lower-order one whenever the chain backed off.
+        When the current context has no successors, the dead-end policy either jumps to
+        a fresh start state (whose last word becomes the next word, at the cost of the
+        start-state entropy) or fails.
+        In a model trained with boundaries, drawing the boundary marker ends a line: it
+        adds no word, and the next step draws the first word of a new line. With
+        end_at_boundary, the chain only stops once the target is met at a line end.
+        With a required word, every draw until it appears is conditioned on reaching it
+        within the word count, and counts the entropy of the conditioned draw.
+        With a character limit, every draw leaves out the words that no longer fit; the
+        entropy this removes, compared with the unconstrained draws, is reported too.
+    */
+    let boundary = model.boundary();
+    let budget = match settings.target {
+        Target::Words(length) => length,
+        Target::MinBits(_) | Target::MinSurprisal(_) => MAX_CHAIN_WORDS,
+    };
+    let missing = |result: &[WordId]| {
+        settings
+            .include
+            .as_ref()
+            .is_some_and(|include| !result.contains(&include.word()))
+    };
+    let failure = || match settings.max_chars {
+        Some(_) => ChainFailure::OverLength,
+        None => ChainFailure::Unreachable,
+    };
+    let (mut context, first, start_entropy) =
+        draw_start(rng, model, settings, &settings.start, &[], Some(budget)).ok_or_else(failure)?;
+    let mut entropy = start_entropy;
+    let mut length_reduction = Entropy::default();
+    let mut reduce = |unconstrained: Entropy, step: Entropy| {
+        /* A draw that left nothing out differs from the unconstrained one by rounding alone. */
+        let difference = |unconstrained: f64, step: f64| match unconstrained - step {
+            difference if difference.abs() < 1e-9 => 0.0,
+            difference => difference,
+        };
+        if settings.max_chars.is_some() {
+            length_reduction += Entropy {
+                shannon_bits: difference(unconstrained.shannon_bits, step.shannon_bits),
+                surprisal_bits: difference(unconstrained.surprisal_bits, step.surprisal_bits),
+            };
+        }
+    };
+    reduce(settings.start.drawn(first), start_entropy);
+    let mut result = first.into_iter().collect::<Vec<WordId>>();
+    let mut at_boundary = false;
+    while !settings.target.reached(result.len(), entropy)
+        || (settings.end_at_boundary && !at_boundary)
+        || missing(&result)
+    {
+        let constrained = missing(&result);
+        let words = budget.saturating_sub(result.len());
+        if constrained && words == 0 {
+            return Err(ChainFailure::Unreachable);
+        }
+        match next_words(model, &context, settings.min_successors) {
+            Some(next_words) => {
+                let (next_word, step) = draw_next(
+                    rng,
+                    model,
+                    settings,
+                    &context,
+                    next_words,
+                    &result,
+                    constrained.then_some(words),
+                )
+                .ok_or_else(failure)?;
+                entropy += step;
+                reduce(next_words.drawn(&next_word, settings.sampling), step);
+                context.shift(next_word);
+                context.mark_boundary(boundary);
+                at_boundary = Some(next_word) == boundary;
+                if !at_boundary {
+                    result.push(next_word);
+                }
+            }
+            None if settings.dead_end == DeadEndPolicy::Restart => {
+                let (restart, first, step) = draw_start(
+                    rng,
+                    model,
+                    settings,
+                    &settings.restart,
+                    &result,
+                    constrained.then_some(words),
+                )
+                .ok_or_else(failure)?;
+                entropy += step;
+                reduce(settings.restart.drawn(first), step);
+                context = restart;
+                result.extend(first);
+                at_boundary = false;
+            }
+            None => return Err(ChainFailure::DeadEnd(context)),
+        }
+        let unbounded = settings.target.is_entropy() || settings.end_at_boundary;
+        if unbounded && result.len() >= MAX_CHAIN_WORDS {
+            return Err(ChainFailure::TooLong);
+        }
+    }
+    Ok(Chain {
+        words: result,
+        entropy,
+        start_entropy,
+        length_reduction,
+    })
+}
+
+/* The most boundary draws in a row followed while grouping draws by the word they add. */
+const MAX_SILENT_DRAWS: usize = 4;
+/*
+    The most draws from every reachable context that the min-entropy is worked out
+    over. Backing off to a short context lets every context draw from a large
+    distribution, which can take too long to search.
+*/
+const MAX_STRENGTH_DRAWS: usize = 1 << 21;
+
+fn add_bits(a: f64, b: f64) -> f64 {
+    /*
+        A function to add two probabilities given in bits, -log2 of each, returning
+        the bits of their sum.
+    */
+    let (low, high) = if a <= b { (a, b) } else { (b, a) };
+    match high.is_finite() {
+        true => low - (1.0 + (low - high).exp2()).log2(),
+        false => low,
+    }
+}
+
+/* Some contexts, each with the bits of the probability of having reached it. */
+type Mixture = Vec<(usize, f64)>;
+
+struct Strength<'m> {
+    model: &'m TransitionModel,
+    /* Whether the chain backs off, see Strength::id. */
+    backoff: bool,
+    boundary: Option<WordId>,
+    /* Every context the chain can reach, by index. */
+    contexts: Vec<Context>,
+    index: HashMap<Context, usize>,
+    /* The draws from each context as (word, bits, next context), or None at a dead end. */
+    edges: Vec<Option<Vec<(WordId, f64, usize)>>>,
+    /*
+        layers[k][id] is the bits of the most likely k words after the context id.
+        Only the last few layers are kept, the older ones are emptied.
+    */
+    layers: Vec<Vec<f64>>,
+    /* restarts[k] is the bits of the most likely k words after a dead end. */
+    restarts: Vec<f64>,
+    /* How many words a mixture of contexts is followed before it is bounded. */
+    depth: usize,
+}
+
+impl Strength<'_> {
+    fn id(&mut self, context: Context) -> usize {
+        /*
+            With backoff, the draws after a context depend only on its longest suffix
+            that the training text has successors for: a longer suffix can only have
+            successors if this one is followed by its last word somewhere. Contexts
+            that share that suffix are one state, which keeps the number of states
+            down where backing off to a short context would reach every longer one.
+        */
+        let context = match self.backoff {
+            true => (0..=context.words().len())
+                .rev()
+                .map(|k| context.suffix(k))
+                .find(|suffix| self.model.successors(suffix).is_some())
+                .unwrap_or_default(),
+            false => context,
+        };
+        match self.index.get(&context) {
+            Some(&id) => id,
+            None => {
+                self.index.insert(context.clone(), self.contexts.len());
+                self.contexts.push(context);
+                self.contexts.len() - 1
+            }
+        }
+    }
+
+    fn starts(&mut self, starts: &Starts) -> Vec<(Option<WordId>, f64, Mixture)> {
+        /*
+            A function to list the first words of a start state with the bits of drawing
+            each and the contexts that open with it.
+        */
+        starts
+            .groups()
+            .map(|(word, probability, contexts)| {
+                let mixture = contexts
+                    .probabilities(SamplingMode::Weighted)
+                    .map(|(context, share)| (self.id(context.clone()), (1.0 / share).log2()))
+                    .collect();
+                (word, (1.0 / probability).log2(), mixture)
+            })
+            .collect()
+    }
+
+    fn continuation(&self, mixture: &[(usize, f64)], words: usize, depth: usize) -> f64 {
+        /*
+            The bits of the most likely `words` words after a mixture of contexts. A
+            mixture that has not come down to a single context within `depth` words is
+            bounded by letting each of its contexts go on with its own most likely
+            words, which can only lower the figure.
+        */
+        match mixture {
+            _ if words == 0 => mixture
+                .iter()
+                .fold(f64::INFINITY, |sum, &(_, bits)| add_bits(sum, bits)),
+            [(id, bits)] => bits + self.layers[words][*id],
+            _ if depth == 0 => mixture.iter().fold(f64::INFINITY, |sum, &(id, bits)| {
+                add_bits(sum, bits + self.layers[words][id])
+            }),
+            _ => self.expand(mixture, words, depth - 1),
+        }
+    }
+
+    fn expand(&self, mixture: &[(usize, f64)], words: usize, depth: usize) -> f64 {
+        /*
+            A function to group the draws from a mixture of contexts by the word they
+            add, so that different paths to the same words add up, and to go on from
+            the most likely group. A boundary draw adds no word, so the draws after it
+            join the same groups. Dead ends, and boundary draws beyond
+            MAX_SILENT_DRAWS in a row, are bounded by their most likely continuation
+            instead.
+        */
+        let mut groups = BTreeMap::<WordId, BTreeMap<usize, f64>>::new();
+        let mut bound = f64::INFINITY;
+        let mut pending = mixture
+            .iter()
+            .map(|&(id, bits)| (id, bits, 0))
+            .collect::<Vec<(usize, f64, usize)>>();
+        while let Some((id, bits, silent)) = pending.pop() {
+            let edges = match &self.edges[id] {
+                Some(edges) => edges,
+                None => {
+                    bound = add_bits(bound, bits + self.restarts[words]);
+                    continue;
+                }
+            };
+            for &(word, step, next) in edges {
+                match Some(word) == self.boundary {
+                    true if silent < MAX_SILENT_DRAWS => {
+                        pending.push((next, bits + step, silent + 1))
+                    }
+                    true => bound = add_bits(bound, bits + step),
+                    false => {
+                        let reached = groups
+                            .entry(word)
+                            .or_default()
+                            .entry(next)
+                            .or_insert(f64::INFINITY);
+                        *reached = add_bits(*reached, bits + step);
+                    }
+                }
+            }
+        }
+        let best = groups
+            .values()
+            .map(|group| {
+                let group = group
+                    .iter()
+                    .map(|(&id, &bits)| (id, bits))
+                    .collect::<Mixture>();
+                self.continuation(&group, words - 1, depth)
+            })
+            .fold(f64::INFINITY, f64::min);
+        add_bits(best, bound)
+    }
+}
+
+pub(crate) fn min_entropy(model: &TransitionModel, settings: &ChainSettings) -> Option<f64> {
+    /*
+        A function to compute the min-entropy of the chain: -log2 of the probability of
+        its most likely passphrase, which is what an attacker who guesses the most
+        likely passphrases first faces. It is found by dynamic programming over the
+        contexts the chain can reach, one word count at a time.
+        Different paths can spell the same words: the contexts of a start state never
+        show in the passphrase, and neither does the end of a line. The draws of such
+        paths are grouped by the words they add and their probabilities summed, for as
+        many words as the model order; where that is not enough, the figure is bounded
+        from below, so it is never higher than the true min-entropy.
+        Returns None for an entropy target, whose length depends on the draws, for
+        the settings that condition or reject draws, and when the contexts the chain
+        can reach have more than MAX_STRENGTH_DRAWS draws between them.
+    */
+    let length = match settings.target {
+        Target::Words(length) => length,
+        Target::MinBits(_) | Target::MinSurprisal(_) => return None,
+    };
+    if settings.include.is_some()
+        || settings.max_chars.is_some()
+        || settings.end_at_boundary
+        || settings.dead_end == DeadEndPolicy::Fail
+    {
+        return None;
+    }
+    let mut strength = Strength {
+        model,
+        backoff: settings.min_successors.is_some(),
+        boundary: model.boundary(),
+        contexts: Vec::new(),
+        index: HashMap::new(),
+        edges: Vec::new(),
+        layers: Vec::new(),
+        restarts: Vec::new(),
+        depth: model.order() + 1,
+    };
+    let starts = strength.starts(&settings.start);
+    let restarts = strength.starts(&settings.restart);
+    let mut draws = 0;
+    while strength.edges.len() < strength.contexts.len() {
+        let context = strength.contexts[strength.edges.len()].clone();
+        let next_words = next_words(model, &context, settings.min_successors);
+        draws += next_words.map_or(0, Distribution::len);
+        if draws > MAX_STRENGTH_DRAWS {
+            return None;
+        }
+        let edges = next_words.map(|next_words| {
+            next_words
+                .probabilities(settings.sampling)
+                .map(|(&word, probability)| {
+                    let mut next =
+                        Context::new([context.words(), &[word]].concat()).suffix(model.order());
+                    next.mark_boundary(strength.boundary);
+                    (word, (1.0 / probability).log2(), strength.id(next))
+                })
+                .collect()
+        });
+        strength.edges.push(edges);
+    }
+    let depth = strength.depth;
+    strength.layers.push(vec![0.0; strength.contexts.len()]);
+    strength.restarts.push(0.0);
+    for words in 1..=length {
+        if let Some(old) = words.checked_sub(depth + 2) {
+            strength.layers[old] = Vec::new();
+        }
+        /* A restart whose first word is empty, at order 0, is bounded by its own draw. */
+        let restart = restarts
+            .iter()
+            .map(|(word, bits, mixture)| match word {
+                Some(_) => bits + strength.continuation(mixture, words - 1, depth),
+                None => *bits,
+            })
+            .fold(f64::INFINITY, f64::min);
+        strength.restarts.push(restart);
+        let layer = (0..strength.contexts.len())
+            .map(|id| match strength.edges[id] {
+                Some(_) => strength.expand(&[(id, 0.0)], words, depth),
+                None => restart,
+            })
+            .collect();
+        strength.layers.push(layer);
+    }
+    let bits = starts
+        .iter()
+        .map(|(word, bits, mixture)| {
+            let words = length - word.is_some() as usize;
+            bits + strength.continuation(mixture, words, depth)
+        })
+        .fold(f64::INFINITY, f64::min);
+    /* The shares of a start's contexts can add up to a hair over 1. */
+    match bits {
+        bits if bits < 1e-9 => Some(0.0),
+        bits => bits.is_finite().then_some(bits),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::corpus::Corpus;
+    use crate::entropy::SamplingMode;
+    use crate::generator::PassphraseGenerator;
+    use crate::rng::EntropySource;
+    use std::collections::HashMap;
+
+    const TEXT: &str =
+        "the cat sat on the mat\nthe dog sat on the log\na cat and a dog\nthe cat ran\n";
+
+    fn check(order: usize, backoff: Option<usize>, tokenizer: &str, sampling: SamplingMode) {
+        /*
+            A function to compare the min-entropy with the frequency of the most common
+            passphrase among many seeded draws.
+        */
+        let mut corpus = Corpus::with_tokenizer(tokenizer.parse().unwrap());
+        corpus.add_text(TEXT);
+        let mut builder = PassphraseGenerator::builder(corpus)
+            .order(order)
+            .rng(EntropySource::from_seed(Some(1)))
+            .length(3)
+            .sampling(sampling);
+        if let Some(min_successors) = backoff {
+            builder = builder.backoff(min_successors);
+        }
+        let mut generator = builder.build().unwrap();
+        let draws = 20000;
+        let mut counts = HashMap::<String, usize>::new();
+        for _ in 0..draws {
+            *counts
+                .entry(generator.generate().unwrap().phrase)
+                .or_default() += 1;
+        }
+        let most = *counts.values().max().unwrap();
+        let observed = (draws as f64 / most as f64).log2();
+        let bits = generator.min_entropy().unwrap();
+        assert!(
+            (bits - observed).abs() < 0.1,
+            "order {order}, backoff {backoff:?}, {tokenizer}: {bits} bits, observed {observed}"
+        );
+    }
+
+    #[test]
+    fn min_entropy_matches_the_most_common_passphrase() {
+        for tokenizer in ["ascii-alphabetic", "unicode-words,lines"] {
+            for order in 0..=2 {
+                check(order, None, tokenizer, SamplingMode::Weighted);
+                check(order, Some(2), tokenizer, SamplingMode::Weighted);
+            }
+            check(2, None, tokenizer, SamplingMode::Uniform);
+        }
+    }
+
+    #[test]
+    fn min_entropy_is_not_reported_for_an_entropy_target() {
+        let generator = PassphraseGenerator::builder(Corpus::from_text(TEXT))
+            .min_bits(10.0)
+            .build()
+            .unwrap();
+        assert_eq!(generator.min_entropy(), None);
+    }
+}
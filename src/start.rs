@@ -0,0 +1,250 @@
+/*
+    Choosing the start state of the chain.
+*/
+
+use crate::distribution::Distribution;
+use crate::entropy::{Entropy, SamplingMode};
+use crate::error::PassphraseError;
+use crate::include::conditioned;
+use crate::model::{first_words, Context, TransitionModel};
+use crate::vocabulary::WordId;
+use rand::{CryptoRng, Rng};
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum StartStrategy {
+    /*
+        Any position in the training text, so first words are weighted by how often
+        they open a start context. Common openings come up often and are less
+        surprising.
+    */
+    Weighted,
+    /* Any distinct first word, each equally likely however often it occurs. */
+    Uniform,
+    /*
+        The first word of a line or sentence, weighted by how many lines open with it.
+        The model must have been trained with boundaries.
+    */
+    LineStarts,
+    /*
+        A given first word. Only the contexts that end with it are left to choose from,
+        and the choice adds no entropy: the word is not a secret.
+    */
+    Word(String),
+}
+
+impl StartStrategy {
+    pub fn default_for(model: &TransitionModel) -> StartStrategy {
+        /*
+            Models trained with boundaries start at line starts, others anywhere.
+        */
+        match model.boundary() {
+            Some(_) => StartStrategy::LineStarts,
+            None => StartStrategy::Weighted,
+        }
+    }
+
+    pub(crate) fn resolve(&self, model: &TransitionModel) -> Result<Starts, PassphraseError> {
+        /*
+            A function to turn the strategy into the distribution start states are drawn
+            from, checking that the model can support it.
+        */
+        let starts = match self {
+            StartStrategy::Weighted => {
+                Starts::new(model.starts(), SamplingMode::Weighted, None)
+            }
+            StartStrategy::Uniform => Starts::new(model.starts(), SamplingMode::Uniform, None),
+            StartStrategy::LineStarts if model.line_starts().is_empty() => {
+                return Err(PassphraseError::NoReachableStates(
+                    "the chain cannot start at a line start: the model was trained without the lines or sentences tokenizer setting"
+                        .to_string(),
+                ))
+            }
+            StartStrategy::LineStarts => {
+                Starts::new(model.line_starts(), SamplingMode::Weighted, None)
+            }
+            StartStrategy::Word(word) => {
+                let id = word_id(model, word)?;
+                let contexts = Distribution::from_counts(
+                    model
+                        .starts()
+                        .iter()
+                        .filter(|(context, _)| context.last() == Some(id) || model.order() == 0)
+                        .map(|(context, count)| (context.clone(), count)),
+                );
+                if contexts.is_empty() {
+                    return Err(PassphraseError::NoReachableStates(format!(
+                        "\"{word}\" is never followed by another word in the training data"
+                    )));
+                }
+                Starts::new(&contexts, SamplingMode::Weighted, Some(id))
+            }
+        };
+        Ok(starts)
+    }
+}
+
+pub(crate) fn word_id(model: &TransitionModel, word: &str) -> Result<WordId, PassphraseError> {
+    /*
+        A function to look a word up after splitting it with the model's tokenizer, so
+        that "Love" finds "love" in a lowercased model.
+    */
+    let words = model.tokenizer().tokenize(word);
+    let normalized = match words.as_slice() {
+        [normalized] => normalized,
+        _ => {
+            return Err(PassphraseError::Usage(format!(
+                "\"{word}\" is not a single word for the tokenizer {}",
+                model.tokenizer()
+            )))
+        }
+    };
+    model.vocabulary().id(normalized).ok_or_else(|| {
+        PassphraseError::NoReachableStates(format!(
+            "\"{word}\" does not occur in the training data"
+        ))
+    })
+}
+
+impl fmt::Display for StartStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StartStrategy::Weighted => write!(f, "weighted"),
+            StartStrategy::Uniform => write!(f, "uniform"),
+            StartStrategy::LineStarts => write!(f, "line-starts"),
+            StartStrategy::Word(word) => write!(f, "first word \"{word}\""),
+        }
+    }
+}
+
+pub(crate) struct Starts {
+    /*
+        The word the passphrase opens with, which is all of the start state that it
+        shows, so the entropy of a start is the entropy of this draw.
+    */
+    first: Distribution<Option<WordId>>,
+    sampling: SamplingMode,
+    /*
+        The start contexts that open with each first word, in the order of `first`,
+        weighted by how many positions share them. Which one is drawn shapes the next
+        draws but never appears in the passphrase, so it adds no entropy.
+    */
+    contexts: Vec<Distribution<Context>>,
+}
+
+impl Starts {
+    fn new(
+        contexts: &Distribution<Context>,
+        sampling: SamplingMode,
+        word: Option<WordId>,
+    ) -> Starts {
+        /*
+            Groups start contexts by their last word, or by the given first word for
+            order 0, whose empty context cannot carry it.
+        */
+        let first = match word {
+            Some(word) => Distribution::from_counts([(Some(word), 1)]),
+            None => first_words(contexts),
+        };
+        let contexts = first
+            .iter()
+            .map(|(&word, _)| {
+                Distribution::from_counts(
+                    contexts
+                        .iter()
+                        .filter(|(context, _)| context.last().or(word) == word)
+                        .map(|(context, count)| (context.clone(), count)),
+                )
+            })
+            .collect();
+        Starts {
+            first,
+            sampling,
+            contexts,
+        }
+    }
+
+    pub(crate) fn drawn(&self, first: Option<WordId>) -> Entropy {
+        /* The entropy of a draw that opened with the given word. */
+        self.first.drawn(&first, self.sampling)
+    }
+
+    pub(crate) fn groups(
+        &self,
+    ) -> impl Iterator<Item = (Option<WordId>, f64, &Distribution<Context>)> {
+        /*
+            Every first word with its probability and the start contexts that open with it.
+        */
+        self.first
+            .probabilities(self.sampling)
+            .zip(&self.contexts)
+            .map(|((&word, probability), contexts)| (word, probability, contexts))
+    }
+
+    pub(crate) fn outcomes(&self) -> impl Iterator<Item = (&Context, Option<WordId>, f64)> {
+        /*
+            Every start context with the word it opens with and its probability.
+        */
+        self.first
+            .probabilities(self.sampling)
+            .zip(&self.contexts)
+            .flat_map(|((&word, probability), contexts)| {
+                contexts
+                    .probabilities(SamplingMode::Weighted)
+                    .map(move |(context, share)| (context, word, probability * share))
+            })
+    }
+
+    pub(crate) fn pick<R: Rng + CryptoRng + ?Sized>(
+        &self,
+        rng: &mut R,
+    ) -> (Context, Option<WordId>, Entropy) {
+        /*
+            Draws the word the passphrase opens with, then one of the contexts that
+            open with it, with the entropy of the first draw.
+        */
+        let word = *self.first.sample(rng, self.sampling);
+        let index = self
+            .first
+            .iter()
+            .position(|(&first, _)| first == word)
+            .expect("the word was drawn from the first words");
+        let context = self.contexts[index]
+            .sample(rng, SamplingMode::Weighted)
+            .clone();
+        (context, word, self.drawn(word))
+    }
+
+    pub(crate) fn pick_conditioned<R: Rng + CryptoRng + ?Sized>(
+        &self,
+        rng: &mut R,
+        weight: impl Fn(&Context, Option<WordId>) -> f64,
+    ) -> Option<(Context, Option<WordId>, Entropy)> {
+        /*
+            A function to draw a start state with probability proportional to its
+            probability times its weight, returning the entropy of the first word drawn.
+            The context is then drawn among those that open with the word, which adds
+            no entropy. Returns None if every weight is zero.
+        */
+        let weights = self
+            .first
+            .probabilities(self.sampling)
+            .zip(&self.contexts)
+            .map(|((&word, probability), contexts)| {
+                contexts
+                    .probabilities(SamplingMode::Weighted)
+                    .map(|(context, share)| probability * share * weight(context, word))
+                    .collect::<Vec<f64>>()
+            })
+            .collect::<Vec<Vec<f64>>>();
+        let totals = weights
+            .iter()
+            .map(|weights| weights.iter().sum())
+            .collect::<Vec<f64>>();
+        let (index, entropy) = conditioned(rng, &totals)?;
+        let (context, _) = conditioned(rng, &weights[index])?;
+        let (&word, _) = self.first.iter().nth(index)?;
+        let (context, _) = self.contexts[index].iter().nth(context)?;
+        Some((context.clone(), word, entropy))
+    }
+}
@@ -0,0 +1,462 @@
+/*
+    Splitting training text into words.
+
+    Normalization follows the Unicode normalization forms (UAX #15), and segmentation
+    the word and grapheme cluster boundaries of Unicode text segmentation (UAX #29),
+    with the Unicode data of the unicode-normalization and unicode-segmentation crates.
+*/
+
+use crate::error::PassphraseError;
+use std::fmt;
+use std::str::FromStr;
+use unicode_normalization::UnicodeNormalization;
+use unicode_segmentation::UnicodeSegmentation;
+
+/*
+    The marker added at the start of a text and after every line or sentence when
+    boundaries are on. No tokenizer setting produces a word containing "<", so it
+    never collides with a real word.
+*/
+pub const BOUNDARY: &str = "</s>";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Segmentation {
+    /*
+        Split on whitespace and drop every character that is not an ASCII letter, so
+        "I'm" becomes "im" and "Dalí" becomes "dal". This is how models were trained
+        before the tokenizer could be configured.
+    */
+    Ascii,
+    /*
+        Split at the Unicode word boundaries, keeping the letters of any script with
+        their combining marks. Punctuation inside a word, other than the joiners the
+        tokenizer keeps, splits it too.
+    */
+    #[default]
+    Unicode,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Normalization {
+    None,
+    /* Canonical composition (NFC): a letter and a combining accent become one character. */
+    #[default]
+    Nfc,
+    /*
+        Compatibility composition (NFKC): compatibility characters such as "ﬁ" or
+        full-width "Ａ" are also replaced by their plain forms.
+    */
+    Nfkc,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum CaseFolding {
+    Preserve,
+    #[default]
+    Lowercase,
+    /* Lowercase, then fold the letters with no single lowercase form: "ß" becomes "ss". */
+    Fold,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Tokenizer {
+    pub segmentation: Segmentation,
+    pub normalization: Normalization,
+    /* Keep an apostrophe between two letters, so "I'm" and "l'homme" stay one word. */
+    pub apostrophes: bool,
+    /* Keep a hyphen between two letters, so "rock-solid" stays one word. */
+    pub hyphens: bool,
+    /* Treat digits as letters instead of word breaks. */
+    pub digits: bool,
+    pub case: CaseFolding,
+    /* End a unit of text, marked with BOUNDARY, at every line break. */
+    pub lines: bool,
+    /* End a unit of text, marked with BOUNDARY, after ".", "!", "?" and "…". */
+    pub sentences: bool,
+}
+
+impl Default for Tokenizer {
+    fn default() -> Tokenizer {
+        Tokenizer {
+            segmentation: Segmentation::Unicode,
+            normalization: Normalization::Nfc,
+            apostrophes: true,
+            hyphens: false,
+            digits: false,
+            case: CaseFolding::Lowercase,
+            lines: false,
+            sentences: false,
+        }
+    }
+}
+
+fn is_apostrophe(c: char) -> bool {
+    matches!(c, '\'' | '\u{2019}' | '\u{2bc}')
+}
+
+fn is_hyphen(c: char) -> bool {
+    matches!(c, '-' | '\u{2010}' | '\u{2011}')
+}
+
+fn is_sentence_end(c: char) -> bool {
+    matches!(c, '.' | '!' | '?' | '\u{2026}')
+}
+
+impl Tokenizer {
+    pub fn ascii() -> Tokenizer {
+        /*
+            The tokenizer models were trained with before it could be configured:
+            ASCII letters only, lowercased.
+        */
+        Tokenizer {
+            segmentation: Segmentation::Ascii,
+            normalization: Normalization::None,
+            apostrophes: false,
+            hyphens: false,
+            digits: false,
+            case: CaseFolding::Lowercase,
+            lines: false,
+            sentences: false,
+        }
+    }
+
+    pub fn normalize(&self, text: &str) -> String {
+        /*
+            A function to apply the normalization form to a text.
+        */
+        match self.normalization {
+            Normalization::None => text.to_string(),
+            Normalization::Nfc => text.nfc().collect(),
+            Normalization::Nfkc => text.nfkc().collect(),
+        }
+    }
+
+    fn is_letter(&self, c: char) -> bool {
+        match self.segmentation {
+            Segmentation::Ascii => c.is_ascii_alphabetic() || (self.digits && c.is_ascii_digit()),
+            Segmentation::Unicode => c.is_alphabetic() || (self.digits && c.is_numeric()),
+        }
+    }
+
+    fn is_letter_cluster(&self, grapheme: &str) -> bool {
+        /*
+            Whether a grapheme cluster is a letter: a base letter with the combining
+            marks that follow it.
+        */
+        grapheme.chars().next().is_some_and(|c| self.is_letter(c))
+    }
+
+    fn joiner(&self, c: char) -> Option<char> {
+        /*
+            A function to recognise the punctuation this tokenizer keeps inside a word,
+            mapped to its ASCII form so that "I’m" and "I'm" are the same word.
+        */
+        if self.apostrophes && is_apostrophe(c) {
+            Some('\'')
+        } else if self.hyphens && is_hyphen(c) {
+            Some('-')
+        } else {
+            None
+        }
+    }
+
+    fn fold_case(&self, word: String) -> String {
+        match self.case {
+            CaseFolding::Preserve => word,
+            CaseFolding::Lowercase => word.to_lowercase(),
+            CaseFolding::Fold => word.to_lowercase().replace('ß', "ss").replace('ς', "σ"),
+        }
+    }
+
+    pub fn tokenize(&self, text: &str) -> Vec<String> {
+        /*
+            A function to split a text into normalized words.
+        */
+        let text = self.normalize(text);
+        match self.segmentation {
+            Segmentation::Ascii => text
+                .split_whitespace()
+                .map(|word| {
+                    self.fold_case(
+                        word.chars()
+                            .filter_map(|c| match self.joiner(c) {
+                                Some(joiner) => Some(joiner),
+                                None => self.is_letter(c).then_some(c),
+                            })
+                            .collect(),
+                    )
+                })
+                .collect(),
+            Segmentation::Unicode => {
+                /*
+                    Walks the grapheme clusters, noting which ones open a word segment.
+                    A new segment ends the word, unless a kept joiner links the two, as
+                    the hyphen of "rock-solid" does.
+                */
+                let mut words = Vec::new();
+                let mut word = String::new();
+                let mut graphemes = text
+                    .split_word_bounds()
+                    .flat_map(|segment| {
+                        segment
+                            .graphemes(true)
+                            .enumerate()
+                            .map(|(i, grapheme)| (i == 0, grapheme))
+                    })
+                    .peekable();
+                while let Some((opens_segment, grapheme)) = graphemes.next() {
+                    if self.is_letter_cluster(grapheme) {
+                        if opens_segment && !word.ends_with(['\'', '-']) && !word.is_empty() {
+                            words.push(self.fold_case(std::mem::take(&mut word)));
+                        }
+                        word.push_str(grapheme);
+                        continue;
+                    }
+                    let mut chars = grapheme.chars();
+                    let joiner = chars
+                        .next()
+                        .filter(|_| chars.next().is_none())
+                        .and_then(|c| self.joiner(c));
+                    match joiner {
+                        Some(joiner)
+                            if !word.is_empty()
+                                && graphemes
+                                    .peek()
+                                    .is_some_and(|&(_, next)| self.is_letter_cluster(next)) =>
+                        {
+                            word.push(joiner)
+                        }
+                        _ if !word.is_empty() => {
+                            words.push(self.fold_case(std::mem::take(&mut word)))
+                        }
+                        _ => {}
+                    }
+                }
+                if !word.is_empty() {
+                    words.push(self.fold_case(word));
+                }
+                words
+            }
+        }
+    }
+
+    pub fn has_boundaries(&self) -> bool {
+        self.lines || self.sentences
+    }
+
+    pub fn tokenize_document(&self, text: &str) -> Vec<String> {
+        /*
+            A function to split a whole training text into words. With line or sentence
+            boundaries on, the text opens with BOUNDARY and every unit that has words is
+            followed by one, so "I'm fly\nto death" becomes
+            "</s> i'm fly </s> to death </s>". Units without words add nothing.
+        */
+        if !self.has_boundaries() {
+            return self.tokenize(text);
+        }
+        let mut words = vec![BOUNDARY.to_string()];
+        let units = text
+            .split(|c: char| (self.lines && c == '\n') || (self.sentences && is_sentence_end(c)));
+        for unit in units {
+            let unit = self.tokenize(unit);
+            if !unit.is_empty() {
+                words.extend(unit);
+                words.push(BOUNDARY.to_string());
+            }
+        }
+        if words.len() == 1 {
+            words.clear();
+        }
+        words
+    }
+}
+
+impl fmt::Display for Tokenizer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /*
+            The settings as a comma-separated list, the form stored with trained models
+            and accepted by --tokenizer.
+        */
+        let mut parts = vec![match self.segmentation {
+            Segmentation::Ascii => "ascii-alphabetic",
+            Segmentation::Unicode => "unicode-words",
+        }];
+        match self.normalization {
+            Normalization::None => {}
+            Normalization::Nfc => parts.push("nfc"),
+            Normalization::Nfkc => parts.push("nfkc"),
+        }
+        for (enabled, name) in [
+            (self.apostrophes, "apostrophes"),
+            (self.hyphens, "hyphens"),
+            (self.digits, "digits"),
+        ] {
+            if enabled {
+                parts.push(name);
+            }
+        }
+        match self.case {
+            CaseFolding::Preserve => {}
+            CaseFolding::Lowercase => parts.push("lowercase"),
+            CaseFolding::Fold => parts.push("casefold"),
+        }
+        for (enabled, name) in [(self.lines, "lines"), (self.sentences, "sentences")] {
+            if enabled {
+                parts.push(name);
+            }
+        }
+        write!(f, "{}", parts.join(","))
+    }
+}
+
+impl FromStr for Tokenizer {
+    type Err = PassphraseError;
+
+    fn from_str(spec: &str) -> Result<Tokenizer, PassphraseError> {
+        /*
+            A function to parse tokenizer settings such as "unicode-words,nfkc,hyphens".
+            Settings that are not listed are off: no normalization, no apostrophes,
+            hyphens or digits, case preserved and no line or sentence boundaries.
+        */
+        let mut tokenizer = Tokenizer {
+            segmentation: Segmentation::Unicode,
+            normalization: Normalization::None,
+            apostrophes: false,
+            hyphens: false,
+            digits: false,
+            case: CaseFolding::Preserve,
+            lines: false,
+            sentences: false,
+        };
+        for part in spec.split(',').map(str::trim) {
+            match part {
+                "ascii-alphabetic" => tokenizer.segmentation = Segmentation::Ascii,
+                "unicode-words" => tokenizer.segmentation = Segmentation::Unicode,
+                "nfc" => tokenizer.normalization = Normalization::Nfc,
+                "nfkc" => tokenizer.normalization = Normalization::Nfkc,
+                "apostrophes" => tokenizer.apostrophes = true,
+                "hyphens" => tokenizer.hyphens = true,
+                "digits" => tokenizer.digits = true,
+                "lowercase" => tokenizer.case = CaseFolding::Lowercase,
+                "casefold" => tokenizer.case = CaseFolding::Fold,
+                "lines" => tokenizer.lines = true,
+                "sentences" => tokenizer.sentences = true,
+                _ => {
+                    return Err(PassphraseError::Usage(format!(
+                        "unknown tokenizer setting \"{part}\""
+                    )))
+                }
+            }
+        }
+        Ok(tokenizer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::corpus::Corpus;
+    use crate::model::TransitionModel;
+
+    fn words(spec: &str, text: &str) -> Vec<String> {
+        spec.parse::<Tokenizer>().unwrap().tokenize_document(text)
+    }
+
+    #[test]
+    fn tokenize_with_each_setting() {
+        let cases: &[(&str, &str, &[&str])] = &[
+            ("ascii-alphabetic,lowercase", "I'm Dalí", &["im", "dal"]),
+            ("ascii-alphabetic,apostrophes,lowercase", "I’m", &["i'm"]),
+            ("ascii-alphabetic,digits", "R2D2 66", &["R2D2", "66"]),
+            ("ascii-alphabetic", "R2D2", &["RD"]),
+            (
+                "unicode-words,nfc,apostrophes,lowercase",
+                "I'm Dalí",
+                &["i'm", "dalí"],
+            ),
+            (
+                "unicode-words,apostrophes,lowercase",
+                "I’m l'homme",
+                &["i'm", "l'homme"],
+            ),
+            ("unicode-words,lowercase", "I'm", &["i", "m"]),
+            (
+                "unicode-words,apostrophes",
+                "the dogs' bone",
+                &["the", "dogs", "bone"],
+            ),
+            ("unicode-words,nfc", "Dali\u{301}", &["Dal\u{ed}"]),
+            ("unicode-words", "Dali\u{301}", &["Dali\u{301}"]),
+            ("unicode-words,nfkc", "ﬁsh Ａb", &["fish", "Ab"]),
+            ("unicode-words,nfc", "ﬁsh Ａb", &["ﬁsh", "Ａb"]),
+            (
+                "unicode-words,hyphens",
+                "rock-solid rock- solid",
+                &["rock-solid", "rock", "solid"],
+            ),
+            ("unicode-words", "rock-solid", &["rock", "solid"]),
+            ("unicode-words,digits", "route 66a", &["route", "66a"]),
+            ("unicode-words", "route 66a r2d2", &["route", "a", "r", "d"]),
+            ("unicode-words", "Straße ΣΟΦΟΣ", &["Straße", "ΣΟΦΟΣ"]),
+            (
+                "unicode-words,lowercase",
+                "Straße ΣΟΦΟΣ",
+                &["straße", "σοφος"],
+            ),
+            (
+                "unicode-words,casefold",
+                "Straße ΣΟΦΟΣ",
+                &["strasse", "σοφοσ"],
+            ),
+            (
+                "unicode-words,apostrophes,lowercase,lines",
+                "I'm fly\n\nto death",
+                &[BOUNDARY, "i'm", "fly", BOUNDARY, "to", "death", BOUNDARY],
+            ),
+            (
+                "unicode-words,lowercase,sentences",
+                "Run. Jump! Why? ok",
+                &[
+                    BOUNDARY, "run", BOUNDARY, "jump", BOUNDARY, "why", BOUNDARY, "ok", BOUNDARY,
+                ],
+            ),
+            ("unicode-words,lines", "\n.\n", &[]),
+        ];
+        for &(spec, text, expected) in cases {
+            assert_eq!(words(spec, text), expected, "{spec} on {text:?}");
+        }
+    }
+
+    #[test]
+    fn settings_survive_model_storage() {
+        let specs = [
+            "ascii-alphabetic",
+            "ascii-alphabetic,lowercase",
+            "unicode-words",
+            "unicode-words,nfc,apostrophes,lowercase",
+            "unicode-words,nfkc,hyphens,digits,casefold",
+            "unicode-words,apostrophes,hyphens,digits,lowercase,lines,sentences",
+        ];
+        let tokenizers = specs
+            .iter()
+            .map(|spec| spec.parse::<Tokenizer>().unwrap())
+            .chain([Tokenizer::default(), Tokenizer::ascii()]);
+        for tokenizer in tokenizers {
+            let spec = tokenizer.to_string();
+            assert_eq!(spec.parse::<Tokenizer>().unwrap(), tokenizer, "{spec}");
+            let mut corpus = Corpus::with_tokenizer(tokenizer);
+            corpus.add_text("the quick brown fox jumps over the lazy dog");
+            let model = TransitionModel::train(&corpus, 1).unwrap();
+            let stored = TransitionModel::from_bytes(&model.to_bytes()).unwrap();
+            assert_eq!(*stored.tokenizer(), tokenizer, "{spec}");
+        }
+        assert_eq!(
+            specs.map(|spec| spec.parse::<Tokenizer>().unwrap().to_string()),
+            specs
+        );
+        assert!(matches!(
+            "unicode-words,stemming".parse::<Tokenizer>(),
+            Err(PassphraseError::Usage(_))
+        ));
+    }
+}
@@ -0,0 +1,75 @@
+/*
+    Detecting passphrases that reproduce the training text word for word.
+*/
+
+use crate::vocabulary::WordId;
+use std::cmp::Ordering;
+
+/* Separates documents in the indexed sequence; it never matches a real word. */
+const DOCUMENT_BREAK: u32 = u32::MAX;
+
+pub struct VerbatimIndex {
+    /* Every document's word ids, joined with DOCUMENT_BREAK. */
+    sequence: Vec<u32>,
+    /* The start of every suffix of the sequence, in lexicographic order of the suffixes. */
+    suffixes: Vec<u32>,
+}
+
+impl VerbatimIndex {
+    pub fn new(documents: &[Vec<WordId>]) -> VerbatimIndex {
+        /*
+            A function to build a suffix array over the training text, so that any run
+            of words can be looked up with a binary search.
+        */
+        let mut sequence = Vec::new();
+        for words in documents {
+            sequence.extend(words.iter().map(|id| id.0));
+            sequence.push(DOCUMENT_BREAK);
+        }
+        let mut suffixes = (0..sequence.len() as u32)
+            .filter(|&i| sequence[i as usize] != DOCUMENT_BREAK)
+            .collect::<Vec<u32>>();
+        suffixes.sort_unstable_by(|&a, &b| sequence[a as usize..].cmp(&sequence[b as usize..]));
+        VerbatimIndex { sequence, suffixes }
+    }
+
+    fn common_prefix(&self, suffix: u32, words: &[u32]) -> usize {
+        self.sequence[suffix as usize..]
+            .iter()
+            .zip(words)
+            .take_while(|(a, b)| a == b)
+            .count()
+    }
+
+    fn longest_match(&self, words: &[u32]) -> usize {
+        /*
+            The longest prefix of words that occurs somewhere in the training text.
+            It is shared with one of the two suffixes either side of where words would
+            be inserted in the suffix array.
+        */
+        let position = self.suffixes.partition_point(|&suffix| {
+            let suffix = &self.sequence[suffix as usize..];
+            suffix[..suffix.len().min(words.len())].cmp(words) == Ordering::Less
+        });
+        let before = position
+            .checked_sub(1)
+            .map_or(0, |i| self.common_prefix(self.suffixes[i], words));
+        let after = self
+            .suffixes
+            .get(position)
+            .map_or(0, |&suffix| self.common_prefix(suffix, words));
+        before.max(after)
+    }
+
+    pub fn longest_run(&self, words: &[WordId]) -> usize {
+        /*
+            A function to measure the longest run of consecutive passphrase words that
+            also appears, in the same order, somewhere in the training text.
+        */
+        let words = words.iter().map(|id| id.0).collect::<Vec<u32>>();
+        (0..words.len())
+            .map(|start| self.longest_match(&words[start..]))
+            .max()
+            .unwrap_or(0)
+    }
+}
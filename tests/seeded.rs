@@ -0,0 +1,41 @@
+/*
+    Pins the output of a seeded generator, so that a change to the sampling or to the
+    random number generator it draws from shows up as a changed passphrase.
+*/
+
+use markov_phrasegens::{Corpus, EntropySource, PassphraseGenerator};
+
+const TEXT: &str = "the quick brown fox jumps over the lazy dog while the lazy cat \
+    sleeps under the warm sun and the quick dog runs after the brown cat over the hill \
+    until the sun goes down and the fox sleeps under the hill";
+
+fn generate(seed: u64) -> Vec<String> {
+    let mut generator = PassphraseGenerator::builder(Corpus::from_text(TEXT))
+        .rng(EntropySource::from_seed(Some(seed)))
+        .length(5)
+        .build()
+        .unwrap();
+    generator
+        .generate_batch(3)
+        .unwrap()
+        .iter()
+        .map(|passphrase| passphrase.to_string())
+        .collect()
+}
+
+#[test]
+fn seeded_output_is_pinned() {
+    assert_eq!(
+        generate(42),
+        [
+            "dog runs after the brown",
+            "the warm sun and the",
+            "lazy cat sleeps under the",
+        ]
+    );
+}
+
+#[test]
+fn same_seed_gives_same_output() {
+    assert_eq!(generate(7), generate(7));
+}